@@ -0,0 +1,392 @@
+use crate::calendar::{regular_session, trading_date};
+use crate::data_download::{BarSize, PriceData, Session};
+use crate::settings::TradingSettings;
+use crate::trading::data::read_data;
+use crate::trading::domain::{Position, TradeBands};
+use crate::trading::positions::PairHolding;
+use crate::trading::sizing::sizing_policy;
+use crate::trading::{trade_bands, wind_down_time};
+use crate::universe::Universe;
+use anyhow::{ensure, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+use tracing::info;
+
+type Bars = Vec<(DateTime<Utc>, Decimal)>;
+
+/// Matches the `TradeGenerator` tick interval.
+const TICK_MINUTES: i64 = 5;
+
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub pnl: Decimal,
+    pub turnover: Decimal,
+    pub max_drawdown: Decimal,
+    pub trades: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PairReport {
+    pub asset_1: String,
+    pub asset_2: String,
+    #[serde(flatten)]
+    pub summary: Summary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacktestReport {
+    pub pairs: Vec<PairReport>,
+    pub aggregate: Summary,
+}
+
+#[derive(Debug, Default)]
+struct Book {
+    shares_1: Decimal,
+    shares_2: Decimal,
+    cash: Decimal,
+    turnover: Decimal,
+    trades: usize,
+    equity: Decimal,
+    peak: Decimal,
+    max_drawdown: Decimal,
+}
+
+impl Book {
+    fn trade_to(
+        &mut self,
+        target_1: Decimal,
+        target_2: Decimal,
+        price_1: Decimal,
+        price_2: Decimal,
+    ) {
+        for (shares, target, price) in [
+            (&mut self.shares_1, target_1, price_1),
+            (&mut self.shares_2, target_2, price_2),
+        ] {
+            let traded = target - *shares;
+            if !traded.is_zero() {
+                self.cash -= traded * price;
+                self.turnover += (traded * price).abs();
+                self.trades += 1;
+                *shares = target;
+            }
+        }
+    }
+
+    fn mark(&mut self, price_1: Decimal, price_2: Decimal) -> Decimal {
+        self.equity = self.cash + self.shares_1 * price_1 + self.shares_2 * price_2;
+        self.peak = self.peak.max(self.equity);
+        self.max_drawdown = self.max_drawdown.max(self.peak - self.equity);
+        self.equity
+    }
+
+    fn apply(
+        &mut self,
+        position: Position,
+        (dollars_1, dollars_2): (Decimal, Decimal),
+        price_1: Decimal,
+        price_2: Decimal,
+    ) {
+        match position {
+            Position::Long if self.shares_1 <= Decimal::ZERO => {
+                self.trade_to(dollars_1 / price_1, -dollars_2 / price_2, price_1, price_2)
+            }
+            Position::Short if self.shares_1 >= Decimal::ZERO => {
+                self.trade_to(-dollars_1 / price_1, dollars_2 / price_2, price_1, price_2)
+            }
+            Position::Flat => self.trade_to(Decimal::ZERO, Decimal::ZERO, price_1, price_2),
+            _ => (),
+        }
+    }
+}
+
+/// Split each ticker's bars into regular-session bars per trading day.
+fn sessions(prices: PriceData) -> Result<HashMap<String, BTreeMap<NaiveDate, Bars>>> {
+    prices
+        .into_iter()
+        .map(|(ticker, bars)| {
+            let mut days: BTreeMap<NaiveDate, Bars> = BTreeMap::new();
+            for bar in bars
+                .into_iter()
+                .filter(|bar| bar.session == Session::Regular)
+            {
+                let t = bar.timestamp;
+                days.entry(trading_date(&t))
+                    .or_default()
+                    .push((t, bar.close));
+            }
+            ensure!(!days.is_empty(), "No regular session bars for {}", ticker);
+            Ok((ticker, days))
+        })
+        .collect()
+}
+
+/// Latest close available at `time` for a series of bars of `bar_length` sorted by bar start.
+fn price_at(
+    bars: &[(DateTime<Utc>, Decimal)],
+    bar_length: Duration,
+    time: DateTime<Utc>,
+) -> Option<Decimal> {
+    bars.iter()
+        .take_while(|(t, _)| *t + bar_length <= time)
+        .last()
+        .map(|(_, p)| *p)
+}
+
+fn summarize(book: &Book) -> Summary {
+    Summary {
+        pnl: book.equity,
+        turnover: book.turnover,
+        max_drawdown: book.max_drawdown,
+        trades: book.trades,
+    }
+}
+
+/// Replay `prices`, made of bars of `bar_size`, through the pairs of `pair_file`.
+pub fn backtest<T: AsRef<Path>>(
+    cash: Decimal,
+    prices: PriceData,
+    bar_size: BarSize,
+    pair_file: T,
+    out_file: T,
+    universe: Option<&Universe>,
+    settings: &TradingSettings,
+) -> Result<BacktestReport> {
+    info!("Starting backtest");
+    let bar_length = bar_size.duration();
+    let prices = sessions(prices)?;
+    let mut trade_pairs = read_data(pair_file)?;
+    if let Some(universe) = universe {
+        trade_pairs = universe.retain_pairs(trade_pairs);
+    }
+    let dates: BTreeSet<NaiveDate> = prices
+        .values()
+        .flat_map(|days| days.keys())
+        .copied()
+        .collect();
+    let mut books: BTreeMap<(String, String), Book> = trade_pairs
+        .iter()
+        .map(|pair| {
+            (
+                (pair.asset_1.clone(), pair.asset_2.clone()),
+                Book::default(),
+            )
+        })
+        .collect();
+    let mut aggregate = Book::default();
+
+    for (previous, date) in dates.iter().zip(dates.iter().skip(1)) {
+        let day_bars = |ticker: &str, date: &NaiveDate| -> Option<&Bars> {
+            prices.get(ticker).and_then(|days| days.get(date))
+        };
+        let open_close: HashMap<String, (Decimal, Decimal)> = prices
+            .keys()
+            .filter_map(|ticker| {
+                let open = day_bars(ticker, date)?.first()?.1;
+                let previous_close = day_bars(ticker, previous)?.last()?.1;
+                Some((ticker.clone(), (open, previous_close)))
+            })
+            .collect();
+        let bands: Vec<TradeBands> = trade_bands(trade_pairs.clone(), &open_close);
+        let sizing = sizing_policy(settings, cash, &bands);
+        let exit_fraction = settings.exit_fraction();
+        let open = regular_session(*date).0;
+        let wind_down = wind_down_time(*date);
+        let mut tick = open + Duration::minutes(1);
+        let empty = Vec::new();
+
+        loop {
+            let winding_down = tick >= wind_down;
+            let time = if winding_down { wind_down } else { tick };
+            for pair in bands.iter() {
+                let bars_1 = day_bars(&pair.asset_1, date).unwrap_or(&empty);
+                let bars_2 = day_bars(&pair.asset_2, date).unwrap_or(&empty);
+                let book = books
+                    .get_mut(&(pair.asset_1.clone(), pair.asset_2.clone()))
+                    .expect("Books exist for every pair");
+                if let Some((p1, p2)) =
+                    price_at(bars_1, bar_length, time).zip(price_at(bars_2, bar_length, time))
+                {
+                    if winding_down {
+                        book.trade_to(Decimal::ZERO, Decimal::ZERO, p1, p2);
+                    } else {
+                        let legs = pair.leg_dollars(sizing.pair_dollars(pair), settings.leg_sizing);
+                        let holding = PairHolding::new(book.shares_1, book.shares_2);
+                        book.apply(pair.signal(&p1, &p2, holding, exit_fraction), legs, p1, p2);
+                    }
+                    book.mark(p1, p2);
+                }
+            }
+            let total_equity: Decimal = books.values().map(|book| book.equity).sum();
+            aggregate.peak = aggregate.peak.max(total_equity);
+            aggregate.max_drawdown = aggregate.max_drawdown.max(aggregate.peak - total_equity);
+            if winding_down {
+                break;
+            }
+            tick = tick + Duration::minutes(TICK_MINUTES);
+        }
+    }
+
+    let pairs: Vec<PairReport> = books
+        .into_iter()
+        .map(|((asset_1, asset_2), book)| {
+            aggregate.turnover += book.turnover;
+            aggregate.trades += book.trades;
+            aggregate.equity += book.equity;
+            PairReport {
+                asset_1,
+                asset_2,
+                summary: summarize(&book),
+            }
+        })
+        .collect();
+    let report = BacktestReport {
+        aggregate: summarize(&aggregate),
+        pairs,
+    };
+    info!(
+        pnl = %report.aggregate.pnl,
+        turnover = %report.aggregate.turnover,
+        max_drawdown = %report.aggregate.max_drawdown,
+        "Backtest complete"
+    );
+    serde_json::to_writer(File::create(out_file)?, &report)?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_download::Bar;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_book() {
+        let mut book = Book::default();
+        let leg = (Decimal::new(100, 0), Decimal::new(100, 0));
+        book.apply(
+            Position::Long,
+            leg,
+            Decimal::new(10, 0),
+            Decimal::new(20, 0),
+        );
+        assert_eq!(book.shares_1, Decimal::new(10, 0));
+        assert_eq!(book.shares_2, Decimal::new(-5, 0));
+        // Already long, so a repeated entry signal is ignored
+        book.apply(
+            Position::Long,
+            leg,
+            Decimal::new(11, 0),
+            Decimal::new(20, 0),
+        );
+        assert_eq!(book.trades, 2);
+        assert_eq!(
+            book.mark(Decimal::new(8, 0), Decimal::new(20, 0)),
+            Decimal::new(-20, 0)
+        );
+        book.apply(
+            Position::Short,
+            leg,
+            Decimal::new(20, 0),
+            Decimal::new(20, 0),
+        );
+        assert_eq!(book.shares_1, Decimal::new(-5, 0));
+        assert_eq!(book.shares_2, Decimal::new(5, 0));
+        assert_eq!(
+            book.mark(Decimal::new(20, 0), Decimal::new(20, 0)),
+            Decimal::new(100, 0)
+        );
+        assert_eq!(book.max_drawdown, Decimal::new(20, 0));
+        book.trade_to(
+            Decimal::ZERO,
+            Decimal::ZERO,
+            Decimal::new(20, 0),
+            Decimal::new(20, 0),
+        );
+        assert_eq!(book.cash, Decimal::new(100, 0));
+        assert_eq!(book.turnover, Decimal::new(900, 0));
+
+        // An exit signal closes both legs
+        book.apply(
+            Position::Long,
+            leg,
+            Decimal::new(10, 0),
+            Decimal::new(20, 0),
+        );
+        book.apply(
+            Position::Flat,
+            leg,
+            Decimal::new(12, 0),
+            Decimal::new(20, 0),
+        );
+        assert_eq!(book.shares_1, Decimal::ZERO);
+        assert_eq!(book.shares_2, Decimal::ZERO);
+        assert_eq!(book.cash, Decimal::new(120, 0));
+    }
+
+    #[test]
+    fn test_price_at() {
+        let bars = vec![
+            (Utc.ymd(2021, 1, 4).and_hms(14, 30, 0), Decimal::new(1, 0)),
+            (Utc.ymd(2021, 1, 4).and_hms(14, 35, 0), Decimal::new(2, 0)),
+        ];
+        let five = Duration::minutes(5);
+        assert_eq!(
+            price_at(&bars, five, Utc.ymd(2021, 1, 4).and_hms(14, 31, 0)),
+            None
+        );
+        assert_eq!(
+            price_at(&bars, five, Utc.ymd(2021, 1, 4).and_hms(14, 36, 0)),
+            Some(Decimal::new(1, 0))
+        );
+        assert_eq!(
+            price_at(&bars, five, Utc.ymd(2021, 1, 4).and_hms(14, 41, 0)),
+            Some(Decimal::new(2, 0))
+        );
+        // A 15-minute bar isn't complete until 14:45
+        assert_eq!(
+            price_at(
+                &bars[..1],
+                Duration::minutes(15),
+                Utc.ymd(2021, 1, 4).and_hms(14, 41, 0)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sessions() {
+        let bar = |timestamp, session| Bar {
+            timestamp,
+            open: Decimal::ONE,
+            high: Decimal::ONE,
+            low: Decimal::ONE,
+            close: Decimal::ONE,
+            volume: Decimal::ZERO,
+            vwap: None,
+            transactions: None,
+            session,
+        };
+        let mut prices = PriceData::new();
+        // Daily bars start at midnight New York time, before the open
+        prices.insert(
+            "AAPL".to_string(),
+            vec![
+                bar(Utc.ymd(2021, 1, 4).and_hms(5, 0, 0), Session::Regular),
+                bar(Utc.ymd(2021, 1, 5).and_hms(5, 0, 0), Session::Regular),
+            ],
+        );
+        let days = sessions(prices.clone()).unwrap();
+        assert_eq!(days["AAPL"].len(), 2);
+
+        prices.insert(
+            "MSFT".to_string(),
+            vec![bar(Utc.ymd(2021, 1, 4).and_hms(13, 0, 0), Session::Pre)],
+        );
+        assert!(sessions(prices).is_err());
+    }
+}
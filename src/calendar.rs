@@ -0,0 +1,182 @@
+use bdays::HolidayCalendar;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::US::Eastern;
+
+/// NYSE holidays and half-days. Unscheduled closures, such as national days of mourning, are not
+/// included.
+#[derive(Debug, Clone, Copy)]
+pub struct Nyse;
+
+impl<T: Datelike + Copy + PartialOrd> HolidayCalendar<T> for Nyse {
+    fn is_holiday(&self, date: T) -> bool {
+        is_holiday(NaiveDate::from_ymd(date.year(), date.month(), date.day()))
+    }
+}
+
+/// The `n`th (1-based) `weekday` of a month.
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u32) -> NaiveDate {
+    let first = NaiveDate::from_ymd(year, month, 1);
+    let offset = (7 + weekday.num_days_from_monday() - first.weekday().num_days_from_monday()) % 7;
+    first + Duration::days(i64::from(offset + 7 * (n - 1)))
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    let mut date = nth_weekday(year, month, weekday, 4);
+    while (date + Duration::days(7)).month() == month {
+        date += Duration::days(7);
+    }
+    date
+}
+
+/// Easter Sunday, by the anonymous Gregorian algorithm.
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd(year, month as u32, day as u32)
+}
+
+/// A fixed-date holiday moved to Friday when on a Saturday and to Monday when on a Sunday.
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Duration::days(1),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+fn is_holiday(date: NaiveDate) -> bool {
+    let year = date.year();
+    let fixed = |month, day| observed(NaiveDate::from_ymd(year, month, day)) == date;
+    // New Year's Day isn't moved back into the previous year when it falls on a Saturday
+    let new_year = NaiveDate::from_ymd(year, 1, 1);
+    (new_year.weekday() != Weekday::Sat && observed(new_year) == date)
+        || (year >= 1998 && nth_weekday(year, 1, Weekday::Mon, 3) == date)
+        || nth_weekday(year, 2, Weekday::Mon, 3) == date
+        || easter(year) - Duration::days(2) == date
+        || last_weekday(year, 5, Weekday::Mon) == date
+        || (year >= 2022 && fixed(6, 19))
+        || fixed(7, 4)
+        || nth_weekday(year, 9, Weekday::Mon, 1) == date
+        || nth_weekday(year, 11, Weekday::Thu, 4) == date
+        || fixed(12, 25)
+}
+
+/// 13:00 close on the day before Independence Day, the day after Thanksgiving and Christmas Eve.
+/// The days before Independence Day and Christmas are only half-days when they fall on Monday to
+/// Thursday, as the exchange is closed on a Friday before a weekend holiday.
+fn early_close(date: NaiveDate) -> Option<NaiveTime> {
+    let early = Some(NaiveTime::from_hms(13, 0, 0));
+    let monday_to_thursday = date.weekday().num_days_from_monday() < 4;
+    match (date.month(), date.day()) {
+        (7, 3) | (12, 24) if monday_to_thursday => early,
+        (11, _) if nth_weekday(date.year(), 11, Weekday::Thu, 4) + Duration::days(1) == date => {
+            early
+        }
+        _ => None,
+    }
+}
+
+fn eastern(date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    Eastern
+        .from_local_date(&date)
+        .and_time(time)
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+/// The exchange-local date of `t`.
+pub fn trading_date(t: &DateTime<Utc>) -> NaiveDate {
+    t.with_timezone(&Eastern).date().naive_local()
+}
+
+/// Open and close of the regular trading session on `date`, assuming the exchange is open.
+pub fn regular_session(date: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let close = early_close(date).unwrap_or_else(|| NaiveTime::from_hms(16, 0, 0));
+    (
+        eastern(date, NaiveTime::from_hms(9, 30, 0)),
+        eastern(date, close),
+    )
+}
+
+/// Open and close of the regular trading session on `date`, or `None` if the exchange is closed.
+pub fn session(date: NaiveDate) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    if Nyse.is_bday(date) {
+        Some(regular_session(date))
+    } else {
+        None
+    }
+}
+
+/// Start of pre-market and end of post-market trading on `date`.
+pub fn extended_hours(date: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    (
+        eastern(date, NaiveTime::from_hms(4, 0, 0)),
+        eastern(date, NaiveTime::from_hms(20, 0, 0)),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_holidays() {
+        let holidays: Vec<NaiveDate> = [
+            (2021, 1, 1),
+            (2021, 1, 18),
+            (2021, 2, 15),
+            (2021, 4, 2),
+            (2021, 5, 31),
+            (2021, 7, 5),
+            (2021, 9, 6),
+            (2021, 11, 25),
+            (2021, 12, 24),
+            (2022, 6, 20),
+            (2023, 1, 2),
+        ]
+        .iter()
+        .map(|&(y, m, d)| NaiveDate::from_ymd(y, m, d))
+        .collect();
+        for date in holidays {
+            assert!(Nyse.is_holiday(date), "{} is a holiday", date);
+            assert!(session(date).is_none());
+        }
+        // New Year's Day 2022 fell on a Saturday and wasn't observed on the Friday before
+        assert!(!Nyse.is_holiday(NaiveDate::from_ymd(2021, 12, 31)));
+        assert!(!Nyse.is_holiday(NaiveDate::from_ymd(2021, 6, 18)));
+        assert!(Nyse.is_bday(NaiveDate::from_ymd(2021, 11, 26)));
+        assert!(!Nyse.is_bday(NaiveDate::from_ymd(2021, 11, 27)));
+    }
+
+    #[test]
+    fn test_sessions() {
+        let (open, close) = session(NaiveDate::from_ymd(2021, 11, 26)).unwrap();
+        assert_eq!(open, Utc.ymd(2021, 11, 26).and_hms(14, 30, 0));
+        assert_eq!(close, Utc.ymd(2021, 11, 26).and_hms(18, 0, 0));
+        // Summer time
+        assert_eq!(
+            regular_session(NaiveDate::from_ymd(2021, 7, 2)).1,
+            Utc.ymd(2021, 7, 2).and_hms(20, 0, 0)
+        );
+        assert_eq!(
+            regular_session(NaiveDate::from_ymd(2019, 7, 3)).1,
+            Utc.ymd(2019, 7, 3).and_hms(17, 0, 0)
+        );
+        assert_eq!(
+            regular_session(NaiveDate::from_ymd(2020, 12, 24)).1,
+            Utc.ymd(2020, 12, 24).and_hms(18, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use crate::data_download::{Bar, PriceData};
+use crate::settings::CalibrationSettings;
+use crate::trading::data::TradePair;
+use crate::universe::Universe;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use csv::{Reader, Writer};
+use rust_decimal::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::{debug, info};
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct Candidate {
+    asset_1: String,
+    asset_2: String,
+    /// Carried through to the calibrated pair, which is calibrated on `ln p1 - beta * ln p2`.
+    #[serde(default)]
+    hedge_ratio: Option<Decimal>,
+}
+
+/// Candidate pairs from `candidates_file`, or every combination of downloaded tickers. With a
+/// universe, only its members are paired, and only within a sector if `same_sector` is set.
+fn candidates(
+    prices: &PriceData,
+    candidates_file: Option<&str>,
+    universe: Option<&Universe>,
+    same_sector: bool,
+) -> Result<Vec<Candidate>> {
+    let in_universe = |ticker: &str| !matches!(universe, Some(u) if u.get(ticker).is_none());
+    match candidates_file {
+        Some(file) => {
+            let mut reader = Reader::from_path(file)?;
+            let candidates: Result<Vec<Candidate>, csv::Error> = reader.deserialize().collect();
+            Ok(candidates?
+                .into_iter()
+                .filter(|c| in_universe(&c.asset_1) && in_universe(&c.asset_2))
+                .collect())
+        }
+        None => {
+            let mut tickers: Vec<&String> = prices.keys().filter(|t| in_universe(t)).collect();
+            tickers.sort();
+            let sector = |ticker: &str| universe.and_then(|u| u.get(ticker)?.sector.as_deref());
+            Ok(tickers
+                .iter()
+                .enumerate()
+                .flat_map(|(i, t1)| {
+                    tickers[i + 1..]
+                        .iter()
+                        .map(move |t2| (t1.to_string(), t2.to_string()))
+                })
+                .filter(|(t1, t2)| {
+                    !same_sector || (sector(t1).is_some() && sector(t1) == sector(t2))
+                })
+                .map(|(asset_1, asset_2)| Candidate {
+                    asset_1,
+                    asset_2,
+                    hedge_ratio: None,
+                })
+                .collect())
+        }
+    }
+}
+
+/// Log close of every bar with a positive close, computed once per ticker since `Decimal::ln`
+/// is slow.
+fn log_closes(bars: &[Bar]) -> Vec<(DateTime<Utc>, Decimal)> {
+    bars.iter()
+        .filter(|bar| bar.close > Decimal::ZERO)
+        .map(|bar| (bar.timestamp, bar.close.ln()))
+        .collect()
+}
+
+/// Log spread `ln p1 - hedge_ratio * ln p2` at every timestamp present in both series of log
+/// closes.
+fn log_spreads(
+    log_closes_1: &[(DateTime<Utc>, Decimal)],
+    log_closes_2: &[(DateTime<Utc>, Decimal)],
+    hedge_ratio: Decimal,
+) -> Vec<Decimal> {
+    let lookup: HashMap<&DateTime<Utc>, &Decimal> = log_closes_2
+        .iter()
+        .map(|(timestamp, ln_p2)| (timestamp, ln_p2))
+        .collect();
+    log_closes_1
+        .iter()
+        .filter_map(|(timestamp, ln_p1)| {
+            lookup
+                .get(timestamp)
+                .map(|ln_p2| ln_p1 - hedge_ratio * *ln_p2)
+        })
+        .collect()
+}
+
+fn mean(xs: &[Decimal]) -> Decimal {
+    xs.iter().sum::<Decimal>() / Decimal::from(xs.len())
+}
+
+fn std_dev(xs: &[Decimal]) -> Decimal {
+    let m = mean(xs);
+    let variance =
+        xs.iter().map(|x| (x - m) * (x - m)).sum::<Decimal>() / Decimal::from(xs.len() - 1);
+    variance.sqrt().unwrap_or_default()
+}
+
+/// Long-term spread, short-term spread and epsilon for a single series of log spreads.
+fn calibrate_spreads(
+    spreads: &[Decimal],
+    settings: &CalibrationSettings,
+) -> Option<(Decimal, Decimal, Decimal)> {
+    if spreads.len() < settings.min_observations.max(2) {
+        return None;
+    }
+    let start = spreads
+        .len()
+        .saturating_sub(settings.short_term_bars.max(2));
+    let short_term = &spreads[start..];
+    let lt_spread = mean(spreads);
+    let st_spread = mean(short_term);
+    let epsilon = std_dev(short_term) * settings.epsilon_multiplier;
+    if epsilon.is_zero() {
+        return None;
+    }
+    Some((lt_spread, st_spread, epsilon))
+}
+
+pub fn calibrate<T: AsRef<Path>>(
+    prices: PriceData,
+    out_file: T,
+    settings: &CalibrationSettings,
+    universe: Option<&Universe>,
+) -> Result<()> {
+    info!("Calibrating pairs");
+    let candidates = candidates(
+        &prices,
+        settings.candidates_file.as_deref(),
+        universe,
+        settings.same_sector,
+    )?;
+    let mut log_prices: HashMap<String, Vec<(DateTime<Utc>, Decimal)>> = HashMap::new();
+    for ticker in candidates.iter().flat_map(|c| vec![&c.asset_1, &c.asset_2]) {
+        if let Some(bars) = prices.get(ticker) {
+            log_prices
+                .entry(ticker.clone())
+                .or_insert_with(|| log_closes(bars));
+        }
+    }
+    let mut writer = Writer::from_path(out_file)?;
+    for Candidate {
+        asset_1,
+        asset_2,
+        hedge_ratio,
+    } in candidates
+    {
+        let series = log_prices.get(&asset_1).zip(log_prices.get(&asset_2));
+        let calibrated = series
+            .map(|(p1, p2)| log_spreads(p1, p2, hedge_ratio.unwrap_or(Decimal::ONE)))
+            .and_then(|spreads| calibrate_spreads(&spreads, settings));
+        match calibrated {
+            Some((original_lt_spread, original_st_spread, epsilon)) => {
+                writer.serialize(TradePair {
+                    asset_1,
+                    asset_2,
+                    original_lt_spread,
+                    original_st_spread,
+                    epsilon,
+                    hedge_ratio,
+                    dollars: None,
+                })?;
+            }
+            None => debug!(%asset_1, %asset_2, "Insufficient data to calibrate pair"),
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_download::Session;
+    use chrono::TimeZone;
+
+    fn bar(timestamp: DateTime<Utc>, close: Decimal) -> Bar {
+        Bar {
+            timestamp,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: Decimal::ZERO,
+            vwap: None,
+            transactions: None,
+            session: Session::Regular,
+        }
+    }
+
+    #[test]
+    fn test_log_spreads() {
+        let prices_1 = vec![
+            bar(Utc.ymd(2021, 1, 4).and_hms(15, 0, 0), Decimal::new(100, 0)),
+            bar(Utc.ymd(2021, 1, 4).and_hms(15, 5, 0), Decimal::new(100, 0)),
+            bar(Utc.ymd(2021, 1, 4).and_hms(15, 10, 0), Decimal::new(100, 0)),
+        ];
+        let prices_2 = vec![
+            bar(Utc.ymd(2021, 1, 4).and_hms(15, 0, 0), Decimal::new(100, 0)),
+            bar(Utc.ymd(2021, 1, 4).and_hms(15, 10, 0), Decimal::new(100, 0)),
+        ];
+        let (log_1, log_2) = (log_closes(&prices_1), log_closes(&prices_2));
+        let spreads = log_spreads(&log_1, &log_2, Decimal::ONE);
+        assert_eq!(spreads, vec![Decimal::ZERO, Decimal::ZERO]);
+        let spreads = log_spreads(&log_1, &log_2, Decimal::new(5, 1));
+        assert_eq!(spreads[0], Decimal::new(100, 0).ln() / Decimal::new(2, 0));
+    }
+
+    #[test]
+    fn test_candidates() {
+        let mut prices = PriceData::new();
+        for ticker in ["AAPL", "MSFT", "XOM", "CVX"].iter() {
+            prices.insert(ticker.to_string(), Vec::new());
+        }
+        assert_eq!(candidates(&prices, None, None, false).unwrap().len(), 6);
+
+        let mut universe =
+            Universe::from_tickers(&["AAPL".to_string(), "MSFT".to_string(), "XOM".to_string()]);
+        universe.members[0].sector = Some("Technology".to_string());
+        universe.members[1].sector = Some("Technology".to_string());
+        universe.members[2].sector = Some("Energy".to_string());
+        assert_eq!(
+            candidates(&prices, None, Some(&universe), false)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            candidates(&prices, None, Some(&universe), true).unwrap(),
+            vec![Candidate {
+                asset_1: "AAPL".to_string(),
+                asset_2: "MSFT".to_string(),
+                hedge_ratio: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_calibrate_spreads() {
+        let settings = CalibrationSettings {
+            candidates_file: None,
+            short_term_bars: 2,
+            epsilon_multiplier: Decimal::new(2, 0),
+            min_observations: 4,
+            same_sector: false,
+            resample: None,
+        };
+        let spreads = vec![
+            Decimal::new(0, 0),
+            Decimal::new(2, 0),
+            Decimal::new(1, 0),
+            Decimal::new(3, 0),
+        ];
+        let (lt, st, epsilon) = calibrate_spreads(&spreads, &settings).unwrap();
+        assert_eq!(lt, Decimal::new(15, 1));
+        assert_eq!(st, Decimal::new(2, 0));
+        assert_eq!(epsilon.round_dp(6), Decimal::new(2828427, 6));
+
+        assert_eq!(calibrate_spreads(&spreads[..3], &settings), None);
+    }
+}
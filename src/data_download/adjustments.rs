@@ -1,124 +1,484 @@
-use crate::data_download::{DividendData, PriceData, SplitData};
+use crate::calendar::trading_date;
+use crate::data_download::rational::{to_decimal, to_rational};
+use crate::data_download::{Bar, DividendData, PriceData, Session, SplitData, SplitRatio};
+use crate::settings::{AdjustmentMode, MissingActionPolicy};
+use anyhow::{anyhow, Result};
 use chrono::prelude::*;
+use num_rational::BigRational;
+use num_traits::One;
 use rust_decimal::prelude::*;
+use serde::Serialize;
+use tracing::warn;
 
+/// A dividend left out of the adjustment factors.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UnappliedDividend {
+    pub ex_date: NaiveDate,
+    pub amount: Decimal,
+    pub reason: &'static str,
+}
+
+/// The last close before `ex_date` from a regular session bar, so that pre-market and
+/// after-hours bars are ignored. Ex-dates on holidays or sessions without regular bars fall back
+/// to the latest earlier session.
+fn reference_close(prices: &[Bar], ex_date: NaiveDate) -> Option<Decimal> {
+    prices
+        .iter()
+        .rev()
+        .find(|bar| bar.session == Session::Regular && trading_date(&bar.timestamp) < ex_date)
+        .map(|bar| bar.close)
+}
+
+/// Exact adjustment factor of each dividend, together with the dividends that couldn't be
+/// applied. Dividends going ex on or before the first bar's day adjust no bars and are skipped.
 fn dividend_adjustments(
-    prices: &[(DateTime<Utc>, Decimal)],
+    prices: &[Bar],
     dividends: &[(NaiveDate, Decimal)],
-) -> Vec<(NaiveDate, Decimal)> {
-    dividends
-        .iter()
-        .map(|div| {
-            prices
-                .iter()
-                .zip(prices.iter().skip(1))
-                .find(|((_, _), (d2, _))| d2.naive_utc().date() == div.0)
-                .map(|((_, p1), (_, _))| (div.0, Decimal::new(1, 0) - (div.1 / p1)))
+) -> (Vec<(NaiveDate, BigRational)>, Vec<UnappliedDividend>) {
+    let first_date = prices.first().map(|bar| trading_date(&bar.timestamp));
+    let last_date = prices.last().map(|bar| trading_date(&bar.timestamp));
+    let mut factors = Vec::new();
+    let mut unapplied = Vec::new();
+    for &(ex_date, amount) in dividends {
+        if matches!(first_date, Some(first_date) if ex_date <= first_date) {
+            continue;
+        }
+        let factor = match last_date {
+            None => Err("no prices"),
+            Some(last_date) if ex_date > last_date => Err("ex-date after the price window"),
+            _ => match reference_close(prices, ex_date) {
+                None => Err("no close before the ex-date"),
+                Some(close) if close <= amount => Err("dividend exceeds the previous close"),
+                Some(close) => Ok(to_rational(close - amount) / to_rational(close)),
+            },
+        };
+        match factor {
+            Ok(factor) => factors.push((ex_date, factor)),
+            Err(reason) => unapplied.push(UnappliedDividend {
+                ex_date,
+                amount,
+                reason,
+            }),
+        }
+    }
+    (factors, unapplied)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    Dividend,
+    Split,
+    ReverseSplit,
+}
+
+impl ActionKind {
+    fn of_split(split: &SplitRatio) -> Self {
+        if split.is_reverse() {
+            ActionKind::ReverseSplit
+        } else {
+            ActionKind::Split
+        }
+    }
+}
+
+/// A raw corporate action as received from the source.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CorporateAction {
+    Dividend {
+        ex_date: NaiveDate,
+        amount: Decimal,
+    },
+    Split {
+        ex_date: NaiveDate,
+        #[serde(flatten)]
+        ratio: SplitRatio,
+    },
+    ReverseSplit {
+        ex_date: NaiveDate,
+        #[serde(flatten)]
+        ratio: SplitRatio,
+    },
+}
+
+/// A factor both as the exact fraction used for adjusting and rounded to a decimal.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Factor {
+    pub exact: String,
+    pub decimal: Decimal,
+}
+
+impl Factor {
+    fn new(factor: &BigRational) -> Result<Self> {
+        Ok(Self {
+            exact: factor.to_string(),
+            decimal: to_decimal(factor)?,
         })
-        .flatten()
-        .collect()
+    }
+}
+
+/// Price factor of a single corporate action.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AdjustmentFactor {
+    pub ex_date: NaiveDate,
+    pub kind: ActionKind,
+    pub factor: Factor,
+}
+
+/// Product of all factors applied to bars from `from` (inclusive, unbounded if unset) until
+/// `until` (exclusive) when adjusting backward. Forward-adjusted bars are instead scaled by this
+/// factor divided by the factor of the first bar.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CumulativeFactor {
+    pub from: Option<NaiveDate>,
+    pub until: NaiveDate,
+    pub factor: Factor,
+}
+
+/// Every input and intermediate step of adjusting a single ticker.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AdjustmentAudit {
+    pub ticker: String,
+    pub mode: AdjustmentMode,
+    pub actions: Vec<CorporateAction>,
+    pub factors: Vec<AdjustmentFactor>,
+    pub cumulative: Vec<CumulativeFactor>,
 }
 
+/// Exact factor of every applicable corporate action, sorted by ex-date.
 fn adjustments(
-    prices: &[(DateTime<Utc>, Decimal)],
+    prices: &[Bar],
     dividends: &[(NaiveDate, Decimal)],
-    splits: &[(NaiveDate, Decimal)],
-) -> Vec<(NaiveDate, Decimal)> {
-    let mut adjustments = dividend_adjustments(prices, dividends);
-    adjustments.extend_from_slice(splits);
+    splits: &[(NaiveDate, SplitRatio)],
+) -> (
+    Vec<(NaiveDate, ActionKind, BigRational)>,
+    Vec<UnappliedDividend>,
+) {
+    let (dividend_factors, unapplied) = dividend_adjustments(prices, dividends);
+    let mut adjustments: Vec<(NaiveDate, ActionKind, BigRational)> =
+        dividend_factors
+            .into_iter()
+            .map(|(ex_date, factor)| (ex_date, ActionKind::Dividend, factor))
+            .chain(splits.iter().map(|(ex_date, split)| {
+                (*ex_date, ActionKind::of_split(split), split.price_factor())
+            }))
+            .collect();
     adjustments.sort_by_key(|x| x.0);
-    adjustments
+    (adjustments, unapplied)
 }
 
-fn cumulative_adjustments(adjustments: &[(NaiveDate, Decimal)]) -> Vec<(NaiveDate, Decimal)> {
-    let mut v: Vec<(NaiveDate, Decimal)> = adjustments
+fn cumulative_adjustments(
+    adjustments: &[(NaiveDate, BigRational)],
+) -> Vec<(NaiveDate, BigRational)> {
+    let mut v: Vec<(NaiveDate, BigRational)> = adjustments
         .iter()
         .rev()
-        .scan(Decimal::new(1, 0), |state, &(date, adj)| {
+        .scan(BigRational::one(), |state, (date, adj)| {
             *state *= adj;
-            Some((date, *state))
+            Some((*date, state.clone()))
         })
         .collect();
     v.reverse();
     v
 }
 
+/// Scale prices by `factor` and volume by its inverse, so that dollar volume is preserved. Only
+/// the results are rounded to decimals.
+fn adjust_bar(bar: &Bar, factor: &BigRational) -> Result<Bar> {
+    let scale = |price: Decimal| to_decimal(&(to_rational(price) * factor));
+    Ok(Bar {
+        timestamp: bar.timestamp,
+        open: scale(bar.open)?,
+        high: scale(bar.high)?,
+        low: scale(bar.low)?,
+        close: scale(bar.close)?,
+        volume: to_decimal(&(to_rational(bar.volume) / factor))?,
+        vwap: bar.vwap.map(scale).transpose()?,
+        transactions: bar.transactions,
+        session: bar.session,
+    })
+}
+
+/// How `adjust_prices` treats a ticker without dividend or split data, e.g. because the request
+/// for it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentStatus {
+    Adjusted,
+    /// Some corporate-action data was missing and treated as no actions.
+    AssumedNoActions,
+    /// Some corporate-action data was missing and the ticker was left out.
+    Skipped,
+}
+
+/// What `adjust_prices` did with a single ticker.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TickerAdjustment {
+    pub ticker: String,
+    pub status: AdjustmentStatus,
+    /// Corporate-action datasets without an entry for this ticker.
+    pub missing: Vec<&'static str>,
+    pub dividends: usize,
+    pub splits: usize,
+    pub unapplied_dividends: Vec<UnappliedDividend>,
+}
+
+#[derive(Debug)]
+pub struct AdjustedPrices {
+    pub prices: PriceData,
+    /// One entry per ticker of the input, sorted by ticker.
+    pub report: Vec<TickerAdjustment>,
+    /// One entry per adjusted ticker, sorted by ticker.
+    pub audit: Vec<AdjustmentAudit>,
+}
+
+fn adjust_ticker(
+    ticker: &str,
+    prices: &[Bar],
+    dividends: &[(NaiveDate, Decimal)],
+    splits: &[(NaiveDate, SplitRatio)],
+    mode: AdjustmentMode,
+) -> Result<(Vec<Bar>, Vec<UnappliedDividend>, AdjustmentAudit)> {
+    let (factors, unapplied) = adjustments(prices, dividends, splits);
+    let factor_pairs: Vec<(NaiveDate, BigRational)> = factors
+        .iter()
+        .map(|(ex_date, _, factor)| (*ex_date, factor.clone()))
+        .collect();
+    let cumulative = cumulative_adjustments(&factor_pairs);
+    // Each bar takes the cumulative factor of the first adjustment after its trading day, so
+    // several adjustments on one day or between two bars are all stepped past.
+    let one = BigRational::one();
+    let mut next = cumulative.iter().peekable();
+    let bar_factors: Vec<&BigRational> = prices
+        .iter()
+        .map(|bar| {
+            let date = trading_date(&bar.timestamp);
+            while matches!(next.peek(), Some((adj_date, _)) if date >= *adj_date) {
+                next.next();
+            }
+            next.peek().copied().map_or(&one, |(_, adj)| adj)
+        })
+        .collect();
+    let base = match mode {
+        AdjustmentMode::Backward => Some(one.clone()),
+        AdjustmentMode::Forward => Some(bar_factors.first().copied().unwrap_or(&one).clone()),
+        AdjustmentMode::Unadjusted => None,
+    };
+    let adjusted = prices
+        .iter()
+        .zip(bar_factors.iter())
+        .map(|(bar, factor)| match base.as_ref() {
+            Some(base) => adjust_bar(bar, &(*factor / base)),
+            None => Ok(bar.clone()),
+        })
+        .collect::<Result<Vec<Bar>>>()?;
+
+    let audit = AdjustmentAudit {
+        ticker: ticker.to_string(),
+        mode,
+        actions: dividends
+            .iter()
+            .map(|&(ex_date, amount)| CorporateAction::Dividend { ex_date, amount })
+            .chain(splits.iter().map(|&(ex_date, ratio)| {
+                if ratio.is_reverse() {
+                    CorporateAction::ReverseSplit { ex_date, ratio }
+                } else {
+                    CorporateAction::Split { ex_date, ratio }
+                }
+            }))
+            .collect(),
+        factors: factors
+            .iter()
+            .map(|(ex_date, kind, factor)| {
+                Ok(AdjustmentFactor {
+                    ex_date: *ex_date,
+                    kind: *kind,
+                    factor: Factor::new(factor)?,
+                })
+            })
+            .collect::<Result<_>>()?,
+        cumulative: cumulative
+            .iter()
+            .enumerate()
+            .map(|(i, (until, factor))| {
+                Ok(CumulativeFactor {
+                    from: i.checked_sub(1).map(|previous| cumulative[previous].0),
+                    until: *until,
+                    factor: Factor::new(factor)?,
+                })
+            })
+            .collect::<Result<_>>()?,
+    };
+    Ok((adjusted, unapplied, audit))
+}
+
 pub fn adjust_prices(
     price_data: PriceData,
     dividend_data: DividendData,
     split_data: SplitData,
-) -> PriceData {
-    price_data
-        .keys()
+    policy: MissingActionPolicy,
+    mode: AdjustmentMode,
+) -> Result<AdjustedPrices> {
+    let mut tickers: Vec<String> = price_data.keys().cloned().collect();
+    tickers.sort();
+    let missing: Vec<(String, Vec<&'static str>)> = tickers
+        .iter()
         .map(|ticker| {
-            let prices = price_data.get(ticker).unwrap();
-            let dividends = dividend_data.get(ticker).unwrap();
-            let splits = split_data.get(ticker).unwrap();
-            let adjustments = adjustments(prices, dividends, splits);
-            let cumulative = cumulative_adjustments(&adjustments);
-            let adjusted_prices: Vec<(DateTime<Utc>, Decimal)> = prices
-                .iter()
-                .scan(
-                    cumulative.iter().peekable(),
-                    |state, (price_date, price)| {
-                        if let Some((adj_date, adj)) = state.peek() {
-                            if &price_date.naive_utc().date() < adj_date {
-                                Some((*price_date, price * adj))
-                            } else {
-                                (*state).next();
-                                if let Some((_, adj)) = state.peek() {
-                                    Some((*price_date, price * adj))
-                                } else {
-                                    Some((*price_date, *price))
-                                }
-                            }
-                        } else {
-                            Some((*price_date, *price))
-                        }
-                    },
-                )
-                .collect();
-            (ticker.clone(), adjusted_prices)
+            let mut missing = Vec::new();
+            if !dividend_data.contains_key(ticker) {
+                missing.push("dividends");
+            }
+            if !split_data.contains_key(ticker) {
+                missing.push("splits");
+            }
+            (ticker.clone(), missing)
         })
-        .collect()
+        .collect();
+    if policy == MissingActionPolicy::Fail {
+        let incomplete: Vec<String> = missing
+            .iter()
+            .filter(|(_, missing)| !missing.is_empty())
+            .map(|(ticker, missing)| format!("{} ({})", ticker, missing.join(", ")))
+            .collect();
+        if !incomplete.is_empty() {
+            return Err(anyhow!(
+                "Missing corporate actions for {}",
+                incomplete.join(", ")
+            ));
+        }
+    }
+
+    let (no_dividends, no_splits) = (Vec::new(), Vec::new());
+    let mut adjusted = AdjustedPrices {
+        prices: PriceData::new(),
+        report: Vec::new(),
+        audit: Vec::new(),
+    };
+    for (ticker, missing) in missing {
+        let status = match (missing.is_empty(), policy) {
+            (true, _) => AdjustmentStatus::Adjusted,
+            (false, MissingActionPolicy::Skip) => AdjustmentStatus::Skipped,
+            (false, _) => AdjustmentStatus::AssumedNoActions,
+        };
+        let dividends = dividend_data.get(&ticker).unwrap_or(&no_dividends);
+        let splits = split_data.get(&ticker).unwrap_or(&no_splits);
+        let mut unapplied_dividends = Vec::new();
+        if status == AdjustmentStatus::Skipped {
+            warn!(%ticker, ?missing, "Skipping ticker without corporate actions");
+        } else {
+            if status == AdjustmentStatus::AssumedNoActions {
+                warn!(%ticker, ?missing, "Assuming no corporate actions");
+            }
+            let (prices, unapplied, audit) =
+                adjust_ticker(&ticker, &price_data[&ticker], dividends, splits, mode)?;
+            for dividend in unapplied.iter() {
+                warn!(
+                    %ticker,
+                    ex_date = %dividend.ex_date,
+                    amount = %dividend.amount,
+                    "Dividend not applied: {}",
+                    dividend.reason
+                );
+            }
+            adjusted.prices.insert(ticker.clone(), prices);
+            adjusted.audit.push(audit);
+            unapplied_dividends = unapplied;
+        }
+        adjusted.report.push(TickerAdjustment {
+            ticker,
+            status,
+            missing,
+            dividends: dividends.len(),
+            splits: splits.len(),
+            unapplied_dividends,
+        });
+    }
+    Ok(adjusted)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::collections::HashMap;
+    use crate::data_download::Session;
+
+    fn ratio(numer: i64, denom: i64) -> BigRational {
+        BigRational::new(numer.into(), denom.into())
+    }
+
+    fn split(from: i64, to: i64) -> SplitRatio {
+        SplitRatio::new(Decimal::new(from, 0), Decimal::new(to, 0)).unwrap()
+    }
+
+    fn bar(timestamp: DateTime<Utc>, close: Decimal) -> Bar {
+        Bar {
+            timestamp,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: Decimal::new(900, 0),
+            vwap: Some(close),
+            transactions: Some(10),
+            session: Session::Regular,
+        }
+    }
 
     #[test]
     fn test_dividend_adjustments() {
         let prices = vec![
-            (Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Decimal::new(10000, 2)),
-            (Utc.ymd(2021, 1, 2).and_hms(0, 0, 0), Decimal::new(9000, 2)),
+            bar(
+                Utc.ymd(2021, 1, 1).and_hms(20, 55, 0),
+                Decimal::new(10000, 2),
+            ),
+            bar(
+                Utc.ymd(2021, 1, 2).and_hms(20, 55, 0),
+                Decimal::new(9000, 2),
+            ),
         ];
         let dividends = vec![(NaiveDate::from_ymd(2021, 1, 2), Decimal::new(1000, 2))];
 
-        let adjustments = dividend_adjustments(&prices, &dividends);
+        let (adjustments, unapplied) = dividend_adjustments(&prices, &dividends);
         assert_eq!(
             adjustments,
-            vec![(NaiveDate::from_ymd(2021, 1, 2), Decimal::new(9, 1))]
+            vec![(NaiveDate::from_ymd(2021, 1, 2), ratio(9, 10))]
         );
+        assert!(unapplied.is_empty());
     }
 
     #[test]
     fn test_adjustments() {
         let prices = vec![
-            (Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Decimal::new(10000, 2)),
-            (Utc.ymd(2021, 1, 2).and_hms(0, 0, 0), Decimal::new(9000, 2)),
-            (Utc.ymd(2021, 1, 3).and_hms(0, 0, 0), Decimal::new(4500, 2)),
+            bar(
+                Utc.ymd(2021, 1, 1).and_hms(20, 55, 0),
+                Decimal::new(10000, 2),
+            ),
+            bar(
+                Utc.ymd(2021, 1, 2).and_hms(20, 55, 0),
+                Decimal::new(9000, 2),
+            ),
+            bar(
+                Utc.ymd(2021, 1, 3).and_hms(20, 55, 0),
+                Decimal::new(4500, 2),
+            ),
         ];
         let dividends = vec![(NaiveDate::from_ymd(2021, 1, 2), Decimal::new(1000, 2))];
-        let splits = vec![(NaiveDate::from_ymd(2021, 1, 3), Decimal::new(5, 1))];
+        let splits = vec![(NaiveDate::from_ymd(2021, 1, 3), split(1, 2))];
 
-        let adjustments = adjustments(&prices, &dividends, &splits);
+        let (adjustments, _) = adjustments(&prices, &dividends, &splits);
         assert_eq!(
             adjustments,
             vec![
-                (NaiveDate::from_ymd(2021, 1, 2), Decimal::new(9, 1)),
-                (NaiveDate::from_ymd(2021, 1, 3), Decimal::new(5, 1))
+                (
+                    NaiveDate::from_ymd(2021, 1, 2),
+                    ActionKind::Dividend,
+                    ratio(9, 10)
+                ),
+                (
+                    NaiveDate::from_ymd(2021, 1, 3),
+                    ActionKind::Split,
+                    ratio(1, 2)
+                )
             ]
         );
     }
@@ -126,48 +486,378 @@ mod test {
     #[test]
     fn test_cumulative_adjustments() {
         let adjustments = vec![
-            (NaiveDate::from_ymd(2021, 1, 2), Decimal::new(9, 1)),
-            (NaiveDate::from_ymd(2021, 1, 3), Decimal::new(5, 1)),
+            (NaiveDate::from_ymd(2021, 1, 2), ratio(9, 10)),
+            (NaiveDate::from_ymd(2021, 1, 3), ratio(1, 2)),
         ];
         let cumulative = cumulative_adjustments(&adjustments);
         assert_eq!(
             cumulative,
             vec![
-                (NaiveDate::from_ymd(2021, 1, 2), Decimal::new(45, 2)),
-                (NaiveDate::from_ymd(2021, 1, 3), Decimal::new(5, 1))
+                (NaiveDate::from_ymd(2021, 1, 2), ratio(9, 20)),
+                (NaiveDate::from_ymd(2021, 1, 3), ratio(1, 2))
             ]
         );
     }
 
-    #[test]
-    fn test_adjust_prices() {
-        let mut prices = HashMap::new();
+    /// A $10 dividend on a $100 close followed by a 2-for-1 split.
+    fn adjust_prices_inputs() -> (PriceData, DividendData, SplitData) {
+        let mut prices = PriceData::new();
         prices.insert(
             "AAPL".to_string(),
             vec![
-                (Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Decimal::new(10000, 2)),
-                (Utc.ymd(2021, 1, 2).and_hms(0, 0, 0), Decimal::new(9000, 2)),
-                (Utc.ymd(2021, 1, 3).and_hms(0, 0, 0), Decimal::new(4500, 2)),
+                bar(
+                    Utc.ymd(2021, 1, 1).and_hms(20, 55, 0),
+                    Decimal::new(10000, 2),
+                ),
+                bar(
+                    Utc.ymd(2021, 1, 2).and_hms(20, 55, 0),
+                    Decimal::new(9000, 2),
+                ),
+                bar(
+                    Utc.ymd(2021, 1, 3).and_hms(20, 55, 0),
+                    Decimal::new(4500, 2),
+                ),
             ],
         );
-        let mut dividends = HashMap::new();
+        let mut dividends = DividendData::new();
         dividends.insert(
             "AAPL".to_string(),
             vec![(NaiveDate::from_ymd(2021, 1, 2), Decimal::new(1000, 2))],
         );
-        let mut splits = HashMap::new();
+        let mut splits = SplitData::new();
         splits.insert(
             "AAPL".to_string(),
-            vec![(NaiveDate::from_ymd(2021, 1, 3), Decimal::new(5, 1))],
+            vec![(NaiveDate::from_ymd(2021, 1, 3), split(1, 2))],
         );
-        let adjusted_prices = adjust_prices(prices, dividends, splits);
+        (prices, dividends, splits)
+    }
+
+    #[test]
+    fn test_adjust_prices() {
+        let (prices, dividends, splits) = adjust_prices_inputs();
+        let adjusted = adjust_prices(
+            prices,
+            dividends,
+            splits,
+            MissingActionPolicy::Fail,
+            AdjustmentMode::Backward,
+        )
+        .unwrap();
+        assert_eq!(adjusted.report[0].status, AdjustmentStatus::Adjusted);
         assert_eq!(
-            adjusted_prices.get("AAPL"),
+            adjusted.prices.get("AAPL"),
             Some(&vec![
-                (Utc.ymd(2021, 1, 1).and_hms(0, 0, 0), Decimal::new(4500, 2)),
-                (Utc.ymd(2021, 1, 2).and_hms(0, 0, 0), Decimal::new(4500, 2)),
-                (Utc.ymd(2021, 1, 3).and_hms(0, 0, 0), Decimal::new(4500, 2)),
+                Bar {
+                    volume: Decimal::new(2000, 0),
+                    ..bar(
+                        Utc.ymd(2021, 1, 1).and_hms(20, 55, 0),
+                        Decimal::new(4500, 2)
+                    )
+                },
+                Bar {
+                    volume: Decimal::new(1800, 0),
+                    ..bar(
+                        Utc.ymd(2021, 1, 2).and_hms(20, 55, 0),
+                        Decimal::new(4500, 2)
+                    )
+                },
+                bar(
+                    Utc.ymd(2021, 1, 3).and_hms(20, 55, 0),
+                    Decimal::new(4500, 2)
+                ),
             ]),
+        );
+        assert_eq!(
+            adjusted.audit[0].cumulative,
+            vec![
+                CumulativeFactor {
+                    from: None,
+                    until: NaiveDate::from_ymd(2021, 1, 2),
+                    factor: Factor {
+                        exact: "9/20".to_string(),
+                        decimal: Decimal::new(45, 2)
+                    }
+                },
+                CumulativeFactor {
+                    from: Some(NaiveDate::from_ymd(2021, 1, 2)),
+                    until: NaiveDate::from_ymd(2021, 1, 3),
+                    factor: Factor {
+                        exact: "1/2".to_string(),
+                        decimal: Decimal::new(5, 1)
+                    }
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forward_adjust_prices() {
+        let (prices, dividends, splits) = adjust_prices_inputs();
+        let adjusted = adjust_prices(
+            prices,
+            dividends,
+            splits,
+            MissingActionPolicy::Fail,
+            AdjustmentMode::Forward,
         )
+        .unwrap();
+        assert_eq!(
+            adjusted.prices.get("AAPL"),
+            Some(&vec![
+                bar(
+                    Utc.ymd(2021, 1, 1).and_hms(20, 55, 0),
+                    Decimal::new(10000, 2)
+                ),
+                Bar {
+                    volume: Decimal::new(810, 0),
+                    ..bar(
+                        Utc.ymd(2021, 1, 2).and_hms(20, 55, 0),
+                        Decimal::new(10000, 2)
+                    )
+                },
+                Bar {
+                    volume: Decimal::new(405, 0),
+                    ..bar(
+                        Utc.ymd(2021, 1, 3).and_hms(20, 55, 0),
+                        Decimal::new(10000, 2)
+                    )
+                },
+            ]),
+        );
+    }
+
+    #[test]
+    fn test_unadjusted_prices() {
+        let (prices, dividends, splits) = adjust_prices_inputs();
+        let adjusted = adjust_prices(
+            prices.clone(),
+            dividends,
+            splits,
+            MissingActionPolicy::Fail,
+            AdjustmentMode::Unadjusted,
+        )
+        .unwrap();
+        assert_eq!(adjusted.prices, prices);
+        assert_eq!(adjusted.audit[0].factors.len(), 2);
+    }
+
+    #[test]
+    fn test_exact_split_factors() {
+        let mut prices = PriceData::new();
+        prices.insert(
+            "AAPL".to_string(),
+            vec![
+                bar(Utc.ymd(2021, 1, 4).and_hms(20, 55, 0), Decimal::new(300, 0)),
+                bar(Utc.ymd(2021, 1, 5).and_hms(20, 55, 0), Decimal::new(100, 0)),
+                bar(
+                    Utc.ymd(2021, 1, 6).and_hms(20, 55, 0),
+                    Decimal::new(1000, 0),
+                ),
+            ],
+        );
+        let mut dividends = DividendData::new();
+        dividends.insert("AAPL".to_string(), vec![]);
+        let mut splits = SplitData::new();
+        splits.insert(
+            "AAPL".to_string(),
+            vec![
+                (NaiveDate::from_ymd(2021, 1, 5), split(1, 3)),
+                (NaiveDate::from_ymd(2021, 1, 6), split(10, 1)),
+            ],
+        );
+        let adjusted = adjust_prices(
+            prices,
+            dividends,
+            splits,
+            MissingActionPolicy::Fail,
+            AdjustmentMode::Backward,
+        )
+        .unwrap();
+        let closes: Vec<Decimal> = adjusted.prices["AAPL"]
+            .iter()
+            .map(|bar| bar.close)
+            .collect();
+        assert_eq!(closes, vec![Decimal::new(1000, 0); 3]);
+        assert_eq!(adjusted.prices["AAPL"][0].volume, Decimal::new(270, 0));
+        assert_eq!(
+            adjusted.audit[0]
+                .factors
+                .iter()
+                .map(|factor| factor.kind)
+                .collect::<Vec<_>>(),
+            vec![ActionKind::Split, ActionKind::ReverseSplit]
+        );
+    }
+
+    #[test]
+    fn test_adjustments_between_bars() {
+        let mut prices = PriceData::new();
+        prices.insert(
+            "AAPL".to_string(),
+            vec![
+                bar(Utc.ymd(2021, 1, 4).and_hms(20, 55, 0), Decimal::new(100, 0)),
+                bar(Utc.ymd(2021, 1, 7).and_hms(20, 55, 0), Decimal::new(45, 0)),
+            ],
+        );
+        let mut dividends = DividendData::new();
+        dividends.insert(
+            "AAPL".to_string(),
+            vec![(NaiveDate::from_ymd(2021, 1, 5), Decimal::new(10, 0))],
+        );
+        let mut splits = SplitData::new();
+        splits.insert(
+            "AAPL".to_string(),
+            vec![(NaiveDate::from_ymd(2021, 1, 6), split(1, 2))],
+        );
+        let adjusted = adjust_prices(
+            prices,
+            dividends,
+            splits,
+            MissingActionPolicy::Fail,
+            AdjustmentMode::Backward,
+        )
+        .unwrap();
+        // Both actions fall between the two bars, so the last bar is left as is
+        let closes: Vec<Decimal> = adjusted.prices["AAPL"]
+            .iter()
+            .map(|bar| bar.close)
+            .collect();
+        assert_eq!(closes, vec![Decimal::new(45, 0); 2]);
+    }
+
+    #[test]
+    fn test_missing_actions() {
+        let mut prices = PriceData::new();
+        for ticker in ["AAPL", "MSFT"].iter() {
+            prices.insert(
+                ticker.to_string(),
+                vec![bar(
+                    Utc.ymd(2021, 1, 1).and_hms(20, 55, 0),
+                    Decimal::new(10000, 2),
+                )],
+            );
+        }
+        let mut dividends = DividendData::new();
+        dividends.insert("AAPL".to_string(), vec![]);
+        dividends.insert("MSFT".to_string(), vec![]);
+        let mut splits = SplitData::new();
+        splits.insert("AAPL".to_string(), vec![]);
+
+        let err = adjust_prices(
+            prices.clone(),
+            dividends.clone(),
+            splits.clone(),
+            MissingActionPolicy::Fail,
+            AdjustmentMode::Backward,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing corporate actions for MSFT (splits)"
+        );
+
+        let adjusted = adjust_prices(
+            prices.clone(),
+            dividends.clone(),
+            splits.clone(),
+            MissingActionPolicy::AssumeNone,
+            AdjustmentMode::Backward,
+        )
+        .unwrap();
+        assert_eq!(adjusted.prices, prices);
+        assert_eq!(
+            adjusted.report[1],
+            TickerAdjustment {
+                ticker: "MSFT".to_string(),
+                status: AdjustmentStatus::AssumedNoActions,
+                missing: vec!["splits"],
+                dividends: 0,
+                splits: 0,
+                unapplied_dividends: vec![],
+            }
+        );
+
+        let adjusted = adjust_prices(
+            prices,
+            dividends,
+            splits,
+            MissingActionPolicy::Skip,
+            AdjustmentMode::Backward,
+        )
+        .unwrap();
+        assert!(adjusted.prices.contains_key("AAPL"));
+        assert!(!adjusted.prices.contains_key("MSFT"));
+        assert_eq!(adjusted.report[1].status, AdjustmentStatus::Skipped);
+    }
+
+    #[test]
+    fn test_dividend_reference_close() {
+        let prices = vec![
+            bar(
+                Utc.ymd(2021, 1, 4).and_hms(20, 55, 0),
+                Decimal::new(10000, 2),
+            ),
+            // After hours, ignored
+            Bar {
+                session: Session::Post,
+                ..bar(Utc.ymd(2021, 1, 4).and_hms(22, 0, 0), Decimal::new(5000, 2))
+            },
+            // Pre-market only, ignored
+            Bar {
+                session: Session::Pre,
+                ..bar(Utc.ymd(2021, 1, 5).and_hms(13, 0, 0), Decimal::new(5000, 2))
+            },
+            bar(
+                Utc.ymd(2021, 1, 6).and_hms(14, 30, 0),
+                Decimal::new(9000, 2),
+            ),
+            bar(
+                Utc.ymd(2021, 1, 6).and_hms(14, 35, 0),
+                Decimal::new(9500, 2),
+            ),
+        ];
+        let dividends = vec![
+            // No session on the ex-date
+            (NaiveDate::from_ymd(2021, 1, 5), Decimal::new(1000, 2)),
+            // Before any bar, so nothing to adjust
+            (NaiveDate::from_ymd(2020, 12, 1), Decimal::new(100, 2)),
+            (NaiveDate::from_ymd(2021, 1, 4), Decimal::new(100, 2)),
+            (NaiveDate::from_ymd(2021, 1, 7), Decimal::new(100, 2)),
+            (NaiveDate::from_ymd(2021, 1, 6), Decimal::new(1000, 2)),
+        ];
+
+        let (adjustments, unapplied) = dividend_adjustments(&prices, &dividends);
+        assert_eq!(
+            adjustments,
+            vec![
+                (NaiveDate::from_ymd(2021, 1, 5), ratio(9, 10)),
+                (NaiveDate::from_ymd(2021, 1, 6), ratio(9, 10))
+            ]
+        );
+        assert_eq!(
+            unapplied,
+            vec![UnappliedDividend {
+                ex_date: NaiveDate::from_ymd(2021, 1, 7),
+                amount: Decimal::new(100, 2),
+                reason: "ex-date after the price window",
+            }]
+        );
+    }
+
+    #[test]
+    fn test_daily_dividend_adjustments() {
+        // Daily bars start at midnight New York time, before the regular open
+        let prices = vec![
+            bar(Utc.ymd(2021, 1, 4).and_hms(5, 0, 0), Decimal::new(10000, 2)),
+            bar(Utc.ymd(2021, 1, 5).and_hms(5, 0, 0), Decimal::new(9000, 2)),
+        ];
+        let dividends = vec![(NaiveDate::from_ymd(2021, 1, 5), Decimal::new(1000, 2))];
+
+        let (adjustments, unapplied) = dividend_adjustments(&prices, &dividends);
+        assert_eq!(
+            adjustments,
+            vec![(NaiveDate::from_ymd(2021, 1, 5), ratio(9, 10))]
+        );
+        assert!(unapplied.is_empty());
     }
 }
@@ -0,0 +1,133 @@
+use crate::calendar::trading_date;
+use crate::data_download::{Bar, BarSize};
+use anyhow::Result;
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use std::collections::BTreeMap;
+use std::fs::{create_dir_all, read_dir, remove_dir_all, File};
+use std::path::{Path, PathBuf};
+
+/// On-disk store of raw, unadjusted bars with one file per bar size, ticker and trading day.
+///
+/// Days without any bars (holidays, halted tickers) are stored as empty files so that they aren't
+/// requested again.
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn new<T: AsRef<Path>>(root: T, bar_size: BarSize) -> Self {
+        let bar_size = format!("{}_{:?}", bar_size.multiplier, bar_size.timespan).to_lowercase();
+        Self {
+            dir: root.as_ref().join(bar_size),
+        }
+    }
+
+    fn path(&self, ticker: &str, date: NaiveDate) -> PathBuf {
+        self.dir.join(ticker).join(format!("{}.json", date))
+    }
+
+    pub fn contains(&self, ticker: &str, date: NaiveDate) -> bool {
+        self.path(ticker, date).exists()
+    }
+
+    pub fn read(&self, ticker: &str, date: NaiveDate) -> Result<Vec<Bar>> {
+        let file = File::open(self.path(ticker, date))?;
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Store `bars` under their trading day, writing an empty entry for every day in `dates`
+    /// without bars.
+    pub fn write(&self, ticker: &str, dates: &[NaiveDate], bars: &[Bar]) -> Result<()> {
+        let mut days: BTreeMap<NaiveDate, Vec<&Bar>> =
+            dates.iter().map(|date| (*date, Vec::new())).collect();
+        for bar in bars {
+            days.entry(trading_date(&bar.timestamp))
+                .or_default()
+                .push(bar);
+        }
+        create_dir_all(self.dir.join(ticker))?;
+        for (date, bars) in days {
+            serde_json::to_writer(File::create(self.path(ticker, date))?, &bars)?;
+        }
+        Ok(())
+    }
+
+    /// Remove every cached bar for `ticker`, across all bar sizes under `root`.
+    pub fn invalidate<T: AsRef<Path>>(root: T, ticker: &str) -> Result<()> {
+        if !root.as_ref().exists() {
+            return Ok(());
+        }
+        for entry in read_dir(root)? {
+            let dir = entry?.path().join(ticker);
+            if dir.exists() {
+                remove_dir_all(dir)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Every weekday from `start_date` to `end_date` inclusive. Holidays are included so that they
+/// get cached as empty days.
+pub fn weekdays(start_date: NaiveDate, end_date: NaiveDate) -> Vec<NaiveDate> {
+    let mut days = Vec::new();
+    let mut date = start_date;
+    while date <= end_date {
+        if !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) {
+            days.push(date);
+        }
+        date += Duration::days(1);
+    }
+    days
+}
+
+/// Group the days for which `cached` is false into runs of consecutive entries of `days`.
+pub fn missing_runs<F: Fn(NaiveDate) -> bool>(
+    days: &[NaiveDate],
+    cached: F,
+) -> Vec<Vec<NaiveDate>> {
+    let mut runs: Vec<Vec<NaiveDate>> = Vec::new();
+    let mut current: Vec<NaiveDate> = Vec::new();
+    for date in days {
+        if cached(*date) {
+            if !current.is_empty() {
+                runs.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(*date);
+        }
+    }
+    if !current.is_empty() {
+        runs.push(current);
+    }
+    runs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_missing_runs() {
+        let days = weekdays(
+            NaiveDate::from_ymd(2021, 1, 1),
+            NaiveDate::from_ymd(2021, 1, 8),
+        );
+        assert_eq!(days.len(), 6);
+        let cached = |date: NaiveDate| date == NaiveDate::from_ymd(2021, 1, 5);
+        assert_eq!(
+            missing_runs(&days, cached),
+            vec![
+                vec![
+                    NaiveDate::from_ymd(2021, 1, 1),
+                    NaiveDate::from_ymd(2021, 1, 4)
+                ],
+                vec![
+                    NaiveDate::from_ymd(2021, 1, 6),
+                    NaiveDate::from_ymd(2021, 1, 7),
+                    NaiveDate::from_ymd(2021, 1, 8)
+                ],
+            ]
+        );
+    }
+}
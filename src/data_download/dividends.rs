@@ -1,5 +1,8 @@
+use crate::data_download::request::FetchFailure;
+use crate::market_data::CorporateActionSource;
+use crate::settings::CorporateActionRange;
 use chrono::NaiveDate;
-use iex::{client::Client, dividends::GetDividends, Range};
+use futures::future::join_all;
 use rust_decimal::prelude::*;
 use std::collections::HashMap;
 use tracing::error;
@@ -7,32 +10,31 @@ use tracing::error;
 pub type DividendData = HashMap<String, Vec<(NaiveDate, Decimal)>>;
 
 pub async fn download_dividends<T: AsRef<str> + std::fmt::Display>(
-    client: &Client<'_>,
+    source: &dyn CorporateActionSource,
     tickers: &[T],
-) -> DividendData {
+    range: CorporateActionRange,
+) -> (DividendData, Vec<FetchFailure>) {
     tracing::debug!("Downloading dividends data");
-    let queries = tickers.iter().map(|ticker| GetDividends {
-        symbol: ticker.as_ref(),
-        range: Range::ThreeMonths,
-    });
-    client
-        .send_all(queries)
+    let futs = tickers
+        .iter()
+        .map(|ticker| source.dividends(ticker.as_ref(), range));
+    let mut failures = Vec::new();
+    let data = join_all(futs)
         .await
         .into_iter()
         .zip(tickers)
-        .map(|(res, ticker)| match res {
-            Ok(v) if v.is_empty() => (ticker.to_string(), vec![]),
-            Ok(dividends) => {
-                let data: Vec<(NaiveDate, Decimal)> = dividends
-                    .iter()
-                    .map(|div| (div.ex_date, div.amount))
-                    .collect();
-                (ticker.to_string(), data)
-            }
+        .filter_map(|(res, ticker)| match res {
+            Ok(data) => Some((ticker.to_string(), data)),
             Err(e) => {
                 error!("Failed to download dividends for {}. Error: {}", ticker, e);
-                (ticker.to_string(), vec![])
+                failures.push(FetchFailure {
+                    ticker: ticker.to_string(),
+                    dataset: "dividends",
+                    error: e.to_string(),
+                });
+                None
             }
         })
-        .collect()
+        .collect();
+    (data, failures)
 }
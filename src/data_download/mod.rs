@@ -1,39 +1,203 @@
-use anyhow::Result;
-use bdays::{calendars::us::USSettlement, HolidayCalendar};
+use crate::calendar::{trading_date, Nyse};
+use crate::market_data::Sources;
+use crate::settings::{CorporateActionRange, DownloadSettings, OutputFormat, UniverseSettings};
+use crate::universe::{filter_universe, Universe};
+use anyhow::{anyhow, Result};
+use bdays::HolidayCalendar;
 use chrono::prelude::*;
-use iex::client::Client as IexClient;
-use polygon::rest::Client as PolygonClient;
-use rust_decimal::Decimal;
 use std::collections::HashMap;
 use std::fs::File;
+use tracing::{info, warn};
 
 mod adjustments;
+mod cache;
 mod dividends;
+mod output;
 mod prices;
+mod quality;
+mod rational;
+mod request;
+mod resample;
 mod splits;
+mod symbols;
 pub use adjustments::*;
+pub use cache::{weekdays, Cache};
 pub use dividends::*;
+pub use output::*;
 pub use prices::*;
+pub use quality::*;
+pub use request::{check_failures, is_transient_http, FetchFailure, RequestError, Requester};
+pub use resample::{read_resampled_price_file, resample};
 pub use splits::*;
+pub use symbols::*;
 
-pub async fn download_data<T: AsRef<str> + std::fmt::Display>(
-    tickers: &[T],
+/// Smallest corporate-action range that reaches back from `today` to `start_date`.
+fn covering_range(start_date: NaiveDate, today: NaiveDate) -> CorporateActionRange {
+    let days = (today - start_date).num_days();
+    if days <= 28 {
+        CorporateActionRange::OneMonth
+    } else if days <= 89 {
+        CorporateActionRange::ThreeMonths
+    } else if days <= 181 {
+        CorporateActionRange::SixMonths
+    } else if days <= 365 {
+        CorporateActionRange::OneYear
+    } else if days <= 730 {
+        CorporateActionRange::TwoYears
+    } else {
+        CorporateActionRange::FiveYears
+    }
+}
+
+/// The configured corporate-action range, widened if needed so that every bar in the price
+/// window can be adjusted.
+fn corporate_action_range(
+    configured: Option<CorporateActionRange>,
+    start_date: NaiveDate,
+    today: NaiveDate,
+) -> CorporateActionRange {
+    let required = covering_range(start_date, today);
+    match configured {
+        Some(range) if range >= required => range,
+        Some(range) => {
+            warn!(
+                ?range,
+                ?required,
+                "Corporate action range doesn't cover the price window, widening"
+            );
+            required
+        }
+        None => required,
+    }
+}
+
+/// Download, adjust and check prices for every member of `universe`, writing those that pass the
+/// universe filters to `out_file`.
+pub async fn download_data(
+    universe: &Universe,
+    sources: &Sources,
+    settings: &DownloadSettings,
+    universe_settings: &UniverseSettings,
+    format: OutputFormat,
     out_file: File,
 ) -> Result<()> {
-    let iex_client = IexClient::from_env()?;
-    let polygon_client = PolygonClient::from_env()?;
-    let cal = USSettlement;
-    let end_date = cal.advance_bdays(Utc::today().naive_utc(), -1);
-    let start_date = cal.advance_bdays(end_date, -100);
-
-    let prices = download_price_data(&polygon_client, tickers, start_date, end_date).await;
-    let dividends = download_dividends(&iex_client, tickers).await;
-    let splits = download_splits(&iex_client, tickers).await;
-    let adjusted = adjust_prices(prices, dividends, splits);
-    let formatted: HashMap<String, (Vec<DateTime<Utc>>, Vec<Decimal>)> = adjusted
+    let cal = Nyse;
+    let today = Utc::today().naive_utc();
+    let end_date = settings
+        .end_date
+        .unwrap_or_else(|| cal.advance_bdays(today, -1));
+    let start_date = settings
+        .start_date
+        .unwrap_or_else(|| cal.advance_bdays(end_date, -settings.lookback));
+    let range = corporate_action_range(settings.corporate_action_range, start_date, today);
+    let bar_size = settings.bar_size();
+    let cache = settings
+        .cache_dir
+        .as_ref()
+        .map(|dir| Cache::new(dir, bar_size));
+
+    let tickers = universe.tickers();
+    let symbol_map = match settings.symbol_changes.as_ref() {
+        Some(path) => SymbolMap::read(path)?,
+        None => SymbolMap::default(),
+    };
+    let histories: HashMap<String, Vec<SymbolSegment>> = tickers
+        .iter()
+        .map(|ticker| (ticker.clone(), symbol_map.history(ticker, start_date)))
+        .collect();
+    let symbols = history_symbols(&histories);
+
+    let (prices, mut failures) = download_price_data(
+        sources.bars.as_ref(),
+        &symbols,
+        start_date,
+        end_date,
+        bar_size,
+        settings.sessions,
+        cache.as_ref(),
+    )
+    .await;
+    let (dividends, dividend_failures) =
+        download_dividends(sources.corporate_actions.as_ref(), &symbols, range).await;
+    let (splits, split_failures) =
+        download_splits(sources.corporate_actions.as_ref(), &symbols, range).await;
+    let prices = stitch(&prices, &histories, |bar| trading_date(&bar.timestamp));
+    let dividends = stitch(&dividends, &histories, |(ex_date, _)| *ex_date);
+    let splits = stitch(&splits, &histories, |(ex_date, _)| *ex_date);
+    failures.extend(dividend_failures);
+    failures.extend(split_failures);
+    check_failures(&failures, settings.fail_on_error)?;
+    let adjusted = adjust_prices(
+        prices,
+        dividends,
+        splits,
+        settings.missing_actions,
+        settings.adjustment,
+    )?;
+    if let Some(audit_file) = settings.audit_file.as_ref() {
+        serde_json::to_writer(File::create(audit_file)?, &adjusted.audit)?;
+    }
+
+    let sessions: Vec<NaiveDate> = weekdays(start_date, end_date)
         .into_iter()
-        .map(|v| (v.0, v.1.into_iter().unzip()))
+        .filter(|date| cal.is_bday(*date))
         .collect();
-    serde_json::to_writer(out_file, &formatted)?;
+    let report = QualityReport {
+        fetch_failures: failures,
+        adjustments: adjusted.report,
+        symbol_changes: symbol_map.changes(&tickers, start_date),
+        ..quality_report(
+            &tickers,
+            &adjusted.prices,
+            &sessions,
+            bar_size,
+            &settings.quality,
+        )
+    };
+    match settings.quality.report_file.as_ref() {
+        Some(report_file) => serde_json::to_writer(File::create(report_file)?, &report)?,
+        None => info!(report = %serde_json::to_string(&report)?, "Data quality report"),
+    }
+    report.check(settings.quality.fail_on_breach)?;
+    let mut prices = filter_universe(universe, adjusted.prices, universe_settings)?;
+    if let Some(to) = settings.resample {
+        prices = resample(&prices, bar_size, to)?;
+    }
+    write_price_data(out_file, prices, format, settings.layout)
+}
+
+/// Drop cached bars for `tickers`, e.g. after a corporate action changed their history.
+pub fn invalidate_cache<T: AsRef<str>>(tickers: &[T], settings: &DownloadSettings) -> Result<()> {
+    let cache_dir = settings
+        .cache_dir
+        .as_ref()
+        .ok_or_else(|| anyhow!("No cache directory configured"))?;
+    for ticker in tickers {
+        info!(ticker = ticker.as_ref(), "Invalidating cached price data");
+        Cache::invalidate(cache_dir, ticker.as_ref())?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_corporate_action_range() {
+        let today = NaiveDate::from_ymd(2021, 6, 1);
+        let start_date = NaiveDate::from_ymd(2021, 1, 4);
+        assert_eq!(
+            corporate_action_range(None, start_date, today),
+            CorporateActionRange::SixMonths
+        );
+        assert_eq!(
+            corporate_action_range(Some(CorporateActionRange::ThreeMonths), start_date, today),
+            CorporateActionRange::SixMonths
+        );
+        assert_eq!(
+            corporate_action_range(Some(CorporateActionRange::TwoYears), start_date, today),
+            CorporateActionRange::TwoYears
+        );
+    }
+}
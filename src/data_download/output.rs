@@ -0,0 +1,516 @@
+use crate::data_download::{Bar, PriceData, Session};
+use crate::settings::{OutputFormat, OutputLayout};
+use anyhow::{anyhow, Result};
+use arrow::array::{
+    Array, ArrayRef, Float64Array, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, TimeUnit};
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use arrow::record_batch::RecordBatch;
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Per-ticker columns of every bar field.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct BarColumns {
+    timestamp: Vec<DateTime<Utc>>,
+    open: Vec<Decimal>,
+    high: Vec<Decimal>,
+    low: Vec<Decimal>,
+    close: Vec<Decimal>,
+    volume: Vec<Decimal>,
+    vwap: Vec<Option<Decimal>>,
+    transactions: Vec<Option<u64>>,
+    /// Missing from files written before bars were labelled.
+    #[serde(default)]
+    session: Vec<Session>,
+}
+
+impl BarColumns {
+    fn new(bars: Vec<Bar>) -> Self {
+        let mut columns = Self::default();
+        for bar in bars {
+            columns.timestamp.push(bar.timestamp);
+            columns.open.push(bar.open);
+            columns.high.push(bar.high);
+            columns.low.push(bar.low);
+            columns.close.push(bar.close);
+            columns.volume.push(bar.volume);
+            columns.vwap.push(bar.vwap);
+            columns.transactions.push(bar.transactions);
+            columns.session.push(bar.session);
+        }
+        columns
+    }
+
+    fn into_bars(self) -> Result<Vec<Bar>> {
+        let length = self.timestamp.len();
+        let lengths = [
+            self.open.len(),
+            self.high.len(),
+            self.low.len(),
+            self.close.len(),
+            self.volume.len(),
+            self.vwap.len(),
+            self.transactions.len(),
+        ];
+        if lengths.iter().any(|l| *l != length)
+            || !(self.session.is_empty() || self.session.len() == length)
+        {
+            return Err(anyhow!(
+                "Bar columns have different lengths: {} timestamps but {:?} and {} sessions",
+                length,
+                lengths,
+                self.session.len()
+            ));
+        }
+        let mut bars = Vec::with_capacity(self.timestamp.len());
+        for (i, timestamp) in self.timestamp.into_iter().enumerate() {
+            bars.push(Bar {
+                timestamp,
+                open: self.open[i],
+                high: self.high[i],
+                low: self.low[i],
+                close: self.close[i],
+                volume: self.volume[i],
+                vwap: self.vwap[i],
+                transactions: self.transactions[i],
+                session: self.session.get(i).copied().unwrap_or_default(),
+            })
+        }
+        Ok(bars)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Series {
+    Ohlcv(BarColumns),
+    /// Timestamps and closes only.
+    Close((Vec<DateTime<Utc>>, Vec<Decimal>)),
+}
+
+/// Bar fields written for each layout, in column order.
+fn fields(layout: OutputLayout) -> &'static [&'static str] {
+    match layout {
+        OutputLayout::Close => &["close"],
+        OutputLayout::Ohlcv => &[
+            "open",
+            "high",
+            "low",
+            "close",
+            "volume",
+            "vwap",
+            "transactions",
+            "session",
+        ],
+    }
+}
+
+fn session_name(session: Session) -> &'static str {
+    match session {
+        Session::Pre => "pre",
+        Session::Regular => "regular",
+        Session::Post => "post",
+    }
+}
+
+fn parse_session(name: &str) -> Result<Session> {
+    match name {
+        "pre" => Ok(Session::Pre),
+        "regular" => Ok(Session::Regular),
+        "post" => Ok(Session::Post),
+        _ => Err(anyhow!("Unknown session {}", name)),
+    }
+}
+
+/// Rows sorted by ticker, then timestamp.
+fn long_rows(data: &PriceData) -> Vec<(&str, &Bar)> {
+    let mut tickers: Vec<&String> = data.keys().collect();
+    tickers.sort();
+    tickers
+        .into_iter()
+        .flat_map(|ticker| data[ticker].iter().map(move |bar| (ticker.as_str(), bar)))
+        .collect()
+}
+
+fn write_csv<W: Write>(writer: W, data: PriceData, layout: OutputLayout) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    let fields = fields(layout);
+    let mut header = vec!["ticker", "timestamp"];
+    header.extend_from_slice(fields);
+    writer.write_record(&header)?;
+    for (ticker, bar) in long_rows(&data) {
+        let mut record = vec![ticker.to_string(), bar.timestamp.to_rfc3339()];
+        record.extend(fields.iter().map(|field| match *field {
+            "open" => bar.open.to_string(),
+            "high" => bar.high.to_string(),
+            "low" => bar.low.to_string(),
+            "close" => bar.close.to_string(),
+            "volume" => bar.volume.to_string(),
+            "vwap" => bar.vwap.map(|v| v.to_string()).unwrap_or_default(),
+            "transactions" => bar.transactions.map(|n| n.to_string()).unwrap_or_default(),
+            "session" => session_name(bar.session).to_string(),
+            _ => unreachable!(),
+        }));
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Arrow IPC file with timestamps as milliseconds since the epoch (UTC) and prices as `f64`.
+fn write_arrow<W: Write>(writer: W, data: PriceData, layout: OutputLayout) -> Result<()> {
+    let rows = long_rows(&data);
+    let decimals = |f: fn(&Bar) -> Decimal| -> ArrayRef {
+        Arc::new(Float64Array::from(
+            rows.iter()
+                .map(|(_, bar)| f(bar).to_f64().unwrap_or(f64::NAN))
+                .collect::<Vec<f64>>(),
+        ))
+    };
+    let mut schema_fields = vec![
+        Field::new("ticker", DataType::Utf8, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            false,
+        ),
+    ];
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(
+            rows.iter()
+                .map(|(ticker, _)| *ticker)
+                .collect::<Vec<&str>>(),
+        )),
+        Arc::new(TimestampMillisecondArray::from(
+            rows.iter()
+                .map(|(_, bar)| bar.timestamp.timestamp_millis())
+                .collect::<Vec<i64>>(),
+        )),
+    ];
+    for field in fields(layout) {
+        let (data_type, nullable, column) = match *field {
+            "open" => (DataType::Float64, false, decimals(|bar| bar.open)),
+            "high" => (DataType::Float64, false, decimals(|bar| bar.high)),
+            "low" => (DataType::Float64, false, decimals(|bar| bar.low)),
+            "close" => (DataType::Float64, false, decimals(|bar| bar.close)),
+            "volume" => (DataType::Float64, false, decimals(|bar| bar.volume)),
+            "vwap" => (
+                DataType::Float64,
+                true,
+                Arc::new(Float64Array::from(
+                    rows.iter()
+                        .map(|(_, bar)| bar.vwap.and_then(|v| v.to_f64()))
+                        .collect::<Vec<Option<f64>>>(),
+                )) as ArrayRef,
+            ),
+            "transactions" => (
+                DataType::UInt64,
+                true,
+                Arc::new(UInt64Array::from(
+                    rows.iter()
+                        .map(|(_, bar)| bar.transactions)
+                        .collect::<Vec<Option<u64>>>(),
+                )) as ArrayRef,
+            ),
+            "session" => (
+                DataType::Utf8,
+                false,
+                Arc::new(StringArray::from(
+                    rows.iter()
+                        .map(|(_, bar)| session_name(bar.session))
+                        .collect::<Vec<&str>>(),
+                )) as ArrayRef,
+            ),
+            _ => unreachable!(),
+        };
+        schema_fields.push(Field::new(*field, data_type, nullable));
+        columns.push(column);
+    }
+    let schema = Arc::new(Schema::new(schema_fields));
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let mut writer = FileWriter::try_new(writer, &schema)?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn write_price_data<W: Write>(
+    writer: W,
+    data: PriceData,
+    format: OutputFormat,
+    layout: OutputLayout,
+) -> Result<()> {
+    match format {
+        OutputFormat::Json => write_json(writer, data, layout),
+        OutputFormat::Csv => write_csv(writer, data, layout),
+        OutputFormat::Arrow => write_arrow(writer, data, layout),
+    }
+}
+
+fn write_json<W: Write>(writer: W, data: PriceData, layout: OutputLayout) -> Result<()> {
+    let formatted: HashMap<String, Series> = data
+        .into_iter()
+        .map(|(ticker, bars)| {
+            let series = match layout {
+                OutputLayout::Close => Series::Close(
+                    bars.into_iter()
+                        .map(|bar| (bar.timestamp, bar.close))
+                        .unzip(),
+                ),
+                OutputLayout::Ohlcv => Series::Ohlcv(BarColumns::new(bars)),
+            };
+            (ticker, series)
+        })
+        .collect();
+    serde_json::to_writer(writer, &formatted)?;
+    Ok(())
+}
+
+/// A row of the long CSV format. Fields missing from the close-only layout are filled from the
+/// close, as in `read_price_data`.
+#[derive(Debug, Deserialize)]
+struct CsvRow {
+    ticker: String,
+    timestamp: DateTime<Utc>,
+    open: Option<Decimal>,
+    high: Option<Decimal>,
+    low: Option<Decimal>,
+    close: Decimal,
+    volume: Option<Decimal>,
+    vwap: Option<Decimal>,
+    transactions: Option<u64>,
+    session: Option<Session>,
+}
+
+fn read_csv<R: Read>(reader: R) -> Result<PriceData> {
+    let mut data = PriceData::new();
+    for row in csv::Reader::from_reader(reader).deserialize() {
+        let row: CsvRow = row?;
+        data.entry(row.ticker).or_default().push(Bar {
+            timestamp: row.timestamp,
+            open: row.open.unwrap_or(row.close),
+            high: row.high.unwrap_or(row.close),
+            low: row.low.unwrap_or(row.close),
+            close: row.close,
+            volume: row.volume.unwrap_or_default(),
+            vwap: row.vwap,
+            transactions: row.transactions,
+            session: row.session.unwrap_or_default(),
+        })
+    }
+    Ok(data)
+}
+
+/// Column `name` of `batch`, if present.
+fn arrow_column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> Result<Option<&'a T>> {
+    match batch.schema().index_of(name) {
+        Ok(index) => batch
+            .column(index)
+            .as_any()
+            .downcast_ref::<T>()
+            .map(Some)
+            .ok_or_else(|| anyhow!("Column {} has an unexpected type", name)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn required_arrow_column<'a, T: 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a T> {
+    arrow_column(batch, name)?.ok_or_else(|| anyhow!("Missing column {}", name))
+}
+
+/// Prices are written as `f64`, so read back the shortest decimal that round-trips.
+fn arrow_decimal(value: f64) -> Result<Decimal> {
+    Decimal::from_str(&value.to_string()).map_err(|_| anyhow!("Invalid price {}", value))
+}
+
+/// Read back an Arrow IPC file written by `write_arrow` in either layout. Fields missing from the
+/// close-only layout are filled from the close, as in `read_price_data`.
+fn read_arrow<R: Read + Seek>(reader: R) -> Result<PriceData> {
+    let mut data = PriceData::new();
+    for batch in FileReader::try_new(reader)? {
+        let batch = batch?;
+        let tickers: &StringArray = required_arrow_column(&batch, "ticker")?;
+        let timestamps: &TimestampMillisecondArray = required_arrow_column(&batch, "timestamp")?;
+        let closes: &Float64Array = required_arrow_column(&batch, "close")?;
+        let open = arrow_column::<Float64Array>(&batch, "open")?;
+        let high = arrow_column::<Float64Array>(&batch, "high")?;
+        let low = arrow_column::<Float64Array>(&batch, "low")?;
+        let volume = arrow_column::<Float64Array>(&batch, "volume")?;
+        let vwap = arrow_column::<Float64Array>(&batch, "vwap")?;
+        let transactions = arrow_column::<UInt64Array>(&batch, "transactions")?;
+        let sessions = arrow_column::<StringArray>(&batch, "session")?;
+        for i in 0..batch.num_rows() {
+            let close = arrow_decimal(closes.value(i))?;
+            let price = |column: Option<&Float64Array>| match column {
+                Some(column) => arrow_decimal(column.value(i)),
+                None => Ok(close),
+            };
+            data.entry(tickers.value(i).to_string())
+                .or_default()
+                .push(Bar {
+                    timestamp: Utc.timestamp_millis(timestamps.value(i)),
+                    open: price(open)?,
+                    high: price(high)?,
+                    low: price(low)?,
+                    close,
+                    volume: match volume {
+                        Some(volume) => arrow_decimal(volume.value(i))?,
+                        None => Decimal::ZERO,
+                    },
+                    vwap: match vwap {
+                        Some(vwap) if !vwap.is_null(i) => Some(arrow_decimal(vwap.value(i))?),
+                        _ => None,
+                    },
+                    transactions: match transactions {
+                        Some(transactions) if !transactions.is_null(i) => {
+                            Some(transactions.value(i))
+                        }
+                        _ => None,
+                    },
+                    session: match sessions {
+                        Some(sessions) => parse_session(sessions.value(i))?,
+                        None => Session::default(),
+                    },
+                })
+        }
+    }
+    Ok(data)
+}
+
+/// Read a file written by `download_data`, choosing the format from its extension.
+pub fn read_price_file<T: AsRef<Path>>(path: T) -> Result<PriceData> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => read_csv(file),
+        Some("arrow") | Some("ipc") => read_arrow(file),
+        _ => read_price_data(file),
+    }
+}
+
+/// Read back a JSON file written by `download_data` in either layout. Bars read from the close-only
+/// layout carry the close as every price and zero volume.
+pub fn read_price_data<R: Read>(reader: R) -> Result<PriceData> {
+    let formatted: HashMap<String, Series> = serde_json::from_reader(reader)?;
+    formatted
+        .into_iter()
+        .map(|(ticker, series)| {
+            let bars = match series {
+                Series::Ohlcv(columns) => columns.into_bars()?,
+                Series::Close((timestamps, closes)) => timestamps
+                    .into_iter()
+                    .zip(closes)
+                    .map(|(timestamp, close)| Bar {
+                        timestamp,
+                        open: close,
+                        high: close,
+                        low: close,
+                        close,
+                        volume: Decimal::ZERO,
+                        vwap: None,
+                        transactions: None,
+                        session: Session::default(),
+                    })
+                    .collect(),
+            };
+            Ok((ticker, bars))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_round_trip() {
+        let bar = Bar {
+            timestamp: Utc.ymd(2021, 1, 4).and_hms(14, 30, 0),
+            open: Decimal::new(10000, 2),
+            high: Decimal::new(10100, 2),
+            low: Decimal::new(9900, 2),
+            close: Decimal::new(10050, 2),
+            volume: Decimal::new(1000, 0),
+            vwap: Some(Decimal::new(10025, 2)),
+            transactions: Some(10),
+            session: Session::Regular,
+        };
+        let mut data = PriceData::new();
+        data.insert("AAPL".to_string(), vec![bar.clone()]);
+
+        let mut buffer = Vec::new();
+        write_json(&mut buffer, data.clone(), OutputLayout::Ohlcv).unwrap();
+        assert_eq!(read_price_data(buffer.as_slice()).unwrap(), data);
+
+        let mut buffer = Vec::new();
+        write_json(&mut buffer, data, OutputLayout::Close).unwrap();
+        let read = read_price_data(buffer.as_slice()).unwrap();
+        assert_eq!(read["AAPL"][0].close, bar.close);
+        assert_eq!(read["AAPL"][0].volume, Decimal::ZERO);
+
+        let malformed = r#"{"AAPL": {
+            "timestamp": ["2021-01-04T14:30:00Z", "2021-01-04T14:35:00Z"],
+            "open": ["100"], "high": ["101"], "low": ["99"], "close": ["100", "101"],
+            "volume": ["10", "10"], "vwap": [null, null], "transactions": [null, null]
+        }}"#;
+        assert!(read_price_data(malformed.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let bar = Bar {
+            timestamp: Utc.ymd(2021, 1, 4).and_hms(14, 30, 0),
+            open: Decimal::new(10000, 2),
+            high: Decimal::new(10100, 2),
+            low: Decimal::new(9900, 2),
+            close: Decimal::new(10050, 2),
+            volume: Decimal::new(1000, 0),
+            vwap: None,
+            transactions: Some(10),
+            session: Session::Post,
+        };
+        let mut data = PriceData::new();
+        data.insert("AAPL".to_string(), vec![bar]);
+
+        let mut buffer = Vec::new();
+        write_csv(&mut buffer, data.clone(), OutputLayout::Ohlcv).unwrap();
+        assert_eq!(read_csv(buffer.as_slice()).unwrap(), data);
+    }
+
+    #[test]
+    fn test_arrow_round_trip() {
+        let bar = Bar {
+            timestamp: Utc.ymd(2021, 1, 4).and_hms(14, 30, 0),
+            open: Decimal::new(10000, 2),
+            high: Decimal::new(10110, 2),
+            low: Decimal::new(9990, 2),
+            close: Decimal::new(10005, 2),
+            volume: Decimal::new(1000, 0),
+            vwap: None,
+            transactions: Some(10),
+            session: Session::Pre,
+        };
+        let mut data = PriceData::new();
+        data.insert("AAPL".to_string(), vec![bar.clone()]);
+
+        let mut buffer = Vec::new();
+        write_arrow(&mut buffer, data.clone(), OutputLayout::Ohlcv).unwrap();
+        assert_eq!(read_arrow(std::io::Cursor::new(buffer)).unwrap(), data);
+
+        let mut buffer = Vec::new();
+        write_arrow(&mut buffer, data, OutputLayout::Close).unwrap();
+        let read = read_arrow(std::io::Cursor::new(buffer)).unwrap();
+        assert_eq!(read["AAPL"][0].open, bar.close);
+        assert_eq!(read["AAPL"][0].session, Session::Regular);
+    }
+}
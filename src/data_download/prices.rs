@@ -1,74 +1,216 @@
-use bdays::{calendars::us::USSettlement, HolidayCalendar};
-use chrono::{DateTime, NaiveDate, Utc};
+use crate::calendar::{extended_hours, regular_session, trading_date};
+use crate::data_download::cache::{missing_runs, weekdays, Cache};
+use crate::data_download::request::FetchFailure;
+use crate::market_data::BarSource;
+use crate::settings::{BarTimespan, SessionFilter};
+use anyhow::Result;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
 use futures::future::join_all;
-use polygon::rest::{Client, GetAggregate, Timespan};
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::iter::once;
+use tracing::error;
 
-pub type PriceData = HashMap<String, Vec<(DateTime<Utc>, Decimal)>>;
+/// Part of the trading day a bar falls in, by exchange local time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Session {
+    Pre,
+    Regular,
+    Post,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::Regular
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Bar {
+    pub timestamp: DateTime<Utc>,
+    pub open: Decimal,
+    pub high: Decimal,
+    pub low: Decimal,
+    pub close: Decimal,
+    pub volume: Decimal,
+    pub vwap: Option<Decimal>,
+    pub transactions: Option<u64>,
+    /// Assigned by `download_price_data`. Bars stored without a session are assumed regular.
+    #[serde(default)]
+    pub session: Session,
+}
+
+pub type PriceData = HashMap<String, Vec<Bar>>;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct BarSize {
+    pub multiplier: u32,
+    pub timespan: BarTimespan,
+}
+
+impl BarSize {
+    /// Length of time covered by a single bar.
+    pub fn duration(&self) -> Duration {
+        let multiplier = i64::from(self.multiplier);
+        match self.timespan {
+            BarTimespan::Minute => Duration::minutes(multiplier),
+            BarTimespan::Hour => Duration::hours(multiplier),
+            BarTimespan::Day => Duration::days(multiplier),
+        }
+    }
+}
+
+/// Session of a bar of `bar_length` starting at `timestamp`. Bars overlapping the regular session
+/// at all, such as daily bars, count as regular.
+pub fn session_of(timestamp: DateTime<Utc>, bar_length: Duration) -> Session {
+    let (open, close) = regular_session(trading_date(&timestamp));
+    if timestamp + bar_length <= open {
+        Session::Pre
+    } else if timestamp >= close {
+        Session::Post
+    } else {
+        Session::Regular
+    }
+}
+
+/// Label every bar with its session and keep those selected by `filter`.
+fn select_sessions(bars: Vec<Bar>, bar_size: BarSize, filter: SessionFilter) -> Vec<Bar> {
+    let bar_length = bar_size.duration();
+    bars.into_iter()
+        .filter_map(|mut bar| {
+            bar.session = session_of(bar.timestamp, bar_length);
+            let keep = match filter {
+                SessionFilter::Regular => bar.session == Session::Regular,
+                SessionFilter::Extended => {
+                    let (start, end) = extended_hours(trading_date(&bar.timestamp));
+                    bar.timestamp + bar_length > start && bar.timestamp < end
+                }
+                SessionFilter::All => true,
+            };
+            if keep {
+                Some(bar)
+            } else {
+                None
+            }
+        })
+        .collect()
+}
 
-#[tracing::instrument(skip(client))]
-async fn download_ticker_price_data(
-    client: &Client<'_>,
+/// Request only the days missing from `cache`, store them, and return the full window from the
+/// cache. Days from today onwards may be incomplete, so they are always requested and never stored.
+#[tracing::instrument(skip(source, cache))]
+async fn cached_ticker_price_data(
+    source: &dyn BarSource,
+    cache: &Cache,
     ticker: &str,
     start_date: NaiveDate,
     end_date: NaiveDate,
-) -> (String, Vec<(DateTime<Utc>, Decimal)>) {
-    tracing::debug!("Downloading price data");
-    let cal = USSettlement;
-    let end_1 = cal.advance_bdays(end_date, -67);
-    let start_2 = cal.advance_bdays(end_date, -66);
-    let end_2 = cal.advance_bdays(end_date, -34);
-    let start_3 = cal.advance_bdays(end_date, -33);
-    // Split into three queries to get around the data limits.
-    // TODO: Build this into the api somehow, perhaps with pagination?
-    let queries = once(
-        GetAggregate::new(ticker, start_date, end_1)
-            .multiplier(5)
-            .timespan(Timespan::Minute)
-            .unadjusted(true)
-            .limit(50000),
-    )
-    .chain(once(
-        GetAggregate::new(ticker, start_2, end_2)
-            .multiplier(5)
-            .timespan(Timespan::Minute)
-            .unadjusted(true)
-            .limit(50000),
-    ))
-    .chain(once(
-        GetAggregate::new(ticker, start_3, end_date)
-            .multiplier(5)
-            .timespan(Timespan::Minute)
-            .unadjusted(true)
-            .limit(50000),
-    ));
+    bar_size: BarSize,
+) -> Result<Vec<Bar>> {
+    let today = trading_date(&Utc::now());
+    let days = weekdays(start_date, end_date);
+    let mut fresh: Vec<Bar> = Vec::new();
+    for run in missing_runs(&days, |date| date < today && cache.contains(ticker, date)) {
+        let (from, to) = (run[0], run[run.len() - 1]);
+        let bars = source.bars(ticker, from, to, bar_size).await?;
+        let complete: Vec<NaiveDate> = run.into_iter().filter(|date| *date < today).collect();
+        let (complete_bars, partial_bars): (Vec<Bar>, Vec<Bar>) = bars
+            .into_iter()
+            .partition(|bar| trading_date(&bar.timestamp) < today);
+        cache.write(ticker, &complete, &complete_bars)?;
+        fresh.extend(partial_bars);
+    }
+    tracing::debug!("Reading cached price data");
+    let mut data: Vec<Bar> = Vec::new();
+    for date in days.into_iter().filter(|date| *date < today) {
+        data.extend(cache.read(ticker, date)?);
+    }
+    data.extend(fresh);
+    Ok(data)
+}
 
-    let data: Vec<(DateTime<Utc>, Decimal)> = client
-        .send_all(queries)
+pub async fn download_price_data<T: AsRef<str>>(
+    source: &dyn BarSource,
+    tickers: &[T],
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    bar_size: BarSize,
+    sessions: SessionFilter,
+    cache: Option<&Cache>,
+) -> (PriceData, Vec<FetchFailure>) {
+    let futs = tickers.iter().map(|ticker| async move {
+        match cache {
+            Some(cache) => {
+                cached_ticker_price_data(
+                    source,
+                    cache,
+                    ticker.as_ref(),
+                    start_date,
+                    end_date,
+                    bar_size,
+                )
+                .await
+            }
+            None => {
+                source
+                    .bars(ticker.as_ref(), start_date, end_date, bar_size)
+                    .await
+            }
+        }
+    });
+    let mut failures = Vec::new();
+    let data = join_all(futs)
         .await
         .into_iter()
-        .filter_map(|res| res.ok())
-        .filter_map(|wrapper| wrapper.results)
-        .flat_map(|aggs| {
-            let data: Vec<(DateTime<Utc>, Decimal)> =
-                aggs.iter().map(|agg| (agg.t, agg.c)).collect();
-            data
+        .zip(tickers)
+        .filter_map(|(res, ticker)| match res {
+            Ok(data) => Some((
+                ticker.as_ref().to_string(),
+                select_sessions(data, bar_size, sessions),
+            )),
+            Err(e) => {
+                error!(
+                    "Failed to download prices for {}. Error: {}",
+                    ticker.as_ref(),
+                    e
+                );
+                failures.push(FetchFailure {
+                    ticker: ticker.as_ref().to_string(),
+                    dataset: "prices",
+                    error: e.to_string(),
+                });
+                None
+            }
         })
         .collect();
-
-    (ticker.to_string(), data)
+    (data, failures)
 }
 
-pub async fn download_price_data<T: AsRef<str>>(
-    client: &Client<'_>,
-    tickers: &[T],
-    start_date: NaiveDate,
-    end_date: NaiveDate,
-) -> PriceData {
-    let futs = tickers
-        .iter()
-        .map(|ticker| download_ticker_price_data(client, ticker.as_ref(), start_date, end_date));
-    join_all(futs).await.into_iter().collect()
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_session_of() {
+        let five = Duration::minutes(5);
+        assert_eq!(
+            session_of(Utc.ymd(2021, 11, 26).and_hms(14, 25, 0), five),
+            Session::Pre
+        );
+        assert_eq!(
+            session_of(Utc.ymd(2021, 11, 26).and_hms(17, 55, 0), five),
+            Session::Regular
+        );
+        assert_eq!(
+            session_of(Utc.ymd(2021, 11, 26).and_hms(18, 0, 0), five),
+            Session::Post
+        );
+        // Daily bars start at midnight
+        assert_eq!(
+            session_of(Utc.ymd(2021, 11, 26).and_hms(5, 0, 0), Duration::days(1)),
+            Session::Regular
+        );
+    }
 }
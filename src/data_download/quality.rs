@@ -0,0 +1,350 @@
+use crate::calendar::{regular_session, trading_date};
+use crate::data_download::{Bar, BarSize, FetchFailure, PriceData, SymbolChange, TickerAdjustment};
+use crate::settings::QualitySettings;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use rust_decimal::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+
+#[derive(Debug, Serialize)]
+pub struct SessionCoverage {
+    pub date: NaiveDate,
+    pub expected_bars: usize,
+    pub actual_bars: usize,
+}
+
+/// A stretch of a regular session without any bars.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Gap {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub minutes: i64,
+}
+
+/// A close-to-close move between consecutive bars of a session above the jump threshold.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Jump {
+    pub timestamp: DateTime<Utc>,
+    pub from: Decimal,
+    pub to: Decimal,
+    pub change: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TickerQuality {
+    pub ticker: String,
+    pub expected_bars: usize,
+    pub actual_bars: usize,
+    pub sessions: Vec<SessionCoverage>,
+    /// Business days without a single regular-session bar.
+    pub missing_sessions: Vec<NaiveDate>,
+    pub largest_gap: Option<Gap>,
+    pub longest_zero_volume_run: usize,
+    pub jumps: Vec<Jump>,
+    /// Thresholds this ticker breached.
+    pub breaches: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QualityReport {
+    pub tickers: Vec<TickerQuality>,
+    /// Requested tickers without any bars.
+    pub missing_tickers: Vec<String>,
+    /// Price, dividend and split requests that failed after retrying.
+    pub fetch_failures: Vec<FetchFailure>,
+    /// How each ticker's prices were adjusted for corporate actions.
+    pub adjustments: Vec<TickerAdjustment>,
+    /// Tickers stitched from several symbols, or delisted, suspended or renamed in the window.
+    pub symbol_changes: Vec<SymbolChange>,
+}
+
+impl QualityReport {
+    pub fn breached(&self) -> bool {
+        !self.missing_tickers.is_empty()
+            || !self.fetch_failures.is_empty()
+            || self.symbol_changes.iter().any(|c| c.status.is_some())
+            || self.tickers.iter().any(|t| !t.breaches.is_empty())
+    }
+
+    /// Error out if a threshold was breached and `fail_on_breach` is set, otherwise log the
+    /// breaches.
+    pub fn check(&self, fail_on_breach: bool) -> Result<()> {
+        if !self.breached() {
+            info!("Data quality checks passed");
+            return Ok(());
+        }
+        let mut summary: Vec<String> = self
+            .tickers
+            .iter()
+            .filter(|t| !t.breaches.is_empty())
+            .map(|t| format!("{} ({})", t.ticker, t.breaches.join(", ")))
+            .collect();
+        if !self.missing_tickers.is_empty() {
+            summary.push(format!("no data for {}", self.missing_tickers.join(", ")));
+        }
+        for f in self.fetch_failures.iter() {
+            summary.push(format!("{} {} failed", f.ticker, f.dataset));
+        }
+        for change in self.symbol_changes.iter() {
+            if let Some(status) = change.status.as_ref() {
+                summary.push(format!("{} {}", change.ticker, status));
+            }
+        }
+        if fail_on_breach {
+            Err(anyhow!(
+                "Data quality thresholds breached: {}",
+                summary.join("; ")
+            ))
+        } else {
+            warn!("Data quality thresholds breached: {}", summary.join("; "));
+            Ok(())
+        }
+    }
+}
+
+/// Number of bars of `bar_size` needed to cover the regular session on `date`.
+fn expected_session_bars(date: NaiveDate, bar_size: BarSize) -> usize {
+    let (open, close) = regular_session(date);
+    let session = (close - open).num_seconds();
+    let bar = bar_size.duration().num_seconds().max(1);
+    ((session + bar - 1) / bar) as usize
+}
+
+fn ticker_quality(
+    ticker: &str,
+    bars: &[Bar],
+    sessions: &[NaiveDate],
+    bar_size: BarSize,
+    settings: &QualitySettings,
+) -> TickerQuality {
+    let bar_length = bar_size.duration();
+    let mut days: BTreeMap<NaiveDate, Vec<&Bar>> = BTreeMap::new();
+    for bar in bars {
+        let date = trading_date(&bar.timestamp);
+        let (open, close) = regular_session(date);
+        if bar.timestamp < close && bar.timestamp + bar_length > open {
+            days.entry(date).or_default().push(bar);
+        }
+    }
+
+    let mut coverage = Vec::new();
+    let mut missing_sessions = Vec::new();
+    let mut largest_gap: Option<Gap> = None;
+    let mut jumps = Vec::new();
+    let mut zero_volume_run = 0;
+    let mut longest_zero_volume_run = 0;
+    for date in sessions {
+        let day = days.get(date).map(Vec::as_slice).unwrap_or_default();
+        coverage.push(SessionCoverage {
+            date: *date,
+            expected_bars: expected_session_bars(*date, bar_size),
+            actual_bars: day.len(),
+        });
+        if day.is_empty() {
+            missing_sessions.push(*date);
+            continue;
+        }
+
+        let (open, close) = regular_session(*date);
+        let mut covered_until = open;
+        let ends = day
+            .iter()
+            .map(|bar| (bar.timestamp, bar.timestamp + bar_length))
+            .chain(std::iter::once((close, close)));
+        for (start, end) in ends {
+            let minutes = (start - covered_until).num_minutes();
+            if minutes > largest_gap.as_ref().map_or(0, |gap| gap.minutes) {
+                largest_gap = Some(Gap {
+                    from: covered_until,
+                    to: start,
+                    minutes,
+                });
+            }
+            covered_until = covered_until.max(end);
+        }
+
+        for (previous, bar) in day.iter().zip(day.iter().skip(1)) {
+            if previous.close.is_zero() {
+                continue;
+            }
+            let change = (bar.close - previous.close) / previous.close;
+            if change.abs() > settings.jump_threshold {
+                jumps.push(Jump {
+                    timestamp: bar.timestamp,
+                    from: previous.close,
+                    to: bar.close,
+                    change,
+                });
+            }
+        }
+
+        for bar in day {
+            if bar.volume.is_zero() {
+                zero_volume_run += 1;
+                longest_zero_volume_run = longest_zero_volume_run.max(zero_volume_run);
+            } else {
+                zero_volume_run = 0;
+            }
+        }
+    }
+
+    let expected_bars = coverage.iter().map(|s| s.expected_bars).sum();
+    let actual_bars = coverage.iter().map(|s| s.actual_bars).sum();
+    let missing_bars: usize = coverage
+        .iter()
+        .map(|s| s.expected_bars.saturating_sub(s.actual_bars))
+        .sum();
+    let mut breaches = Vec::new();
+    if expected_bars > 0 {
+        let missing_ratio = Decimal::from(missing_bars) / Decimal::from(expected_bars);
+        if missing_ratio > settings.max_missing_ratio {
+            breaches.push(format!("{} of bars missing", missing_ratio.round_dp(4)));
+        }
+    }
+    if let Some(gap) = largest_gap.as_ref() {
+        if gap.minutes > settings.max_gap_minutes {
+            breaches.push(format!("{} minute gap at {}", gap.minutes, gap.from));
+        }
+    }
+    if longest_zero_volume_run > settings.max_zero_volume_bars {
+        breaches.push(format!(
+            "{} consecutive zero-volume bars",
+            longest_zero_volume_run
+        ));
+    }
+    if jumps.len() > settings.max_jumps {
+        breaches.push(format!("{} outlier jumps", jumps.len()));
+    }
+
+    TickerQuality {
+        ticker: ticker.to_string(),
+        expected_bars,
+        actual_bars,
+        sessions: coverage,
+        missing_sessions,
+        largest_gap,
+        longest_zero_volume_run,
+        jumps,
+        breaches,
+    }
+}
+
+/// Check the regular-session bars of every requested ticker against the business days in
+/// `sessions`. Fetch failures, adjustments and symbol changes are left for the caller to fill in.
+pub fn quality_report<T: AsRef<str>>(
+    tickers: &[T],
+    prices: &PriceData,
+    sessions: &[NaiveDate],
+    bar_size: BarSize,
+    settings: &QualitySettings,
+) -> QualityReport {
+    let mut report = QualityReport {
+        tickers: Vec::new(),
+        missing_tickers: Vec::new(),
+        fetch_failures: Vec::new(),
+        adjustments: Vec::new(),
+        symbol_changes: Vec::new(),
+    };
+    for ticker in tickers {
+        match prices.get(ticker.as_ref()) {
+            Some(bars) if !bars.is_empty() => report.tickers.push(ticker_quality(
+                ticker.as_ref(),
+                bars,
+                sessions,
+                bar_size,
+                settings,
+            )),
+            _ => report.missing_tickers.push(ticker.as_ref().to_string()),
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_download::Session;
+    use crate::settings::BarTimespan;
+    use chrono::TimeZone;
+
+    fn bar(hour: u32, minute: u32, close: i64, volume: i64) -> Bar {
+        Bar {
+            timestamp: Utc.ymd(2021, 1, 4).and_hms(hour, minute, 0),
+            open: Decimal::new(close, 0),
+            high: Decimal::new(close, 0),
+            low: Decimal::new(close, 0),
+            close: Decimal::new(close, 0),
+            volume: Decimal::new(volume, 0),
+            vwap: None,
+            transactions: None,
+            session: Session::Regular,
+        }
+    }
+
+    #[test]
+    fn test_quality_report() {
+        let bar_size = BarSize {
+            multiplier: 5,
+            timespan: BarTimespan::Minute,
+        };
+        let sessions = vec![
+            NaiveDate::from_ymd(2021, 1, 4),
+            NaiveDate::from_ymd(2021, 1, 5),
+        ];
+        let mut prices = PriceData::new();
+        prices.insert(
+            "AAPL".to_string(),
+            vec![
+                // Pre-market bars are ignored
+                bar(14, 0, 1, 100),
+                bar(14, 30, 100, 100),
+                bar(14, 35, 100, 0),
+                bar(14, 40, 100, 0),
+                bar(15, 30, 120, 100),
+                bar(20, 55, 120, 100),
+            ],
+        );
+        let failures = vec![FetchFailure {
+            ticker: "AAPL".to_string(),
+            dataset: "splits",
+            error: "Not found".to_string(),
+        }];
+        let settings = QualitySettings::default();
+        let report = QualityReport {
+            fetch_failures: failures,
+            ..quality_report(&["AAPL", "MSFT"], &prices, &sessions, bar_size, &settings)
+        };
+
+        assert_eq!(report.missing_tickers, vec!["MSFT".to_string()]);
+        assert_eq!(report.fetch_failures.len(), 1);
+        assert!(report.breached());
+        assert!(report.check(true).is_err());
+
+        let aapl = &report.tickers[0];
+        assert_eq!(aapl.expected_bars, 156);
+        assert_eq!(aapl.actual_bars, 5);
+        assert_eq!(aapl.sessions[0].actual_bars, 5);
+        assert_eq!(aapl.missing_sessions, vec![NaiveDate::from_ymd(2021, 1, 5)]);
+        assert_eq!(
+            aapl.largest_gap,
+            Some(Gap {
+                from: Utc.ymd(2021, 1, 4).and_hms(15, 35, 0),
+                to: Utc.ymd(2021, 1, 4).and_hms(20, 55, 0),
+                minutes: 320,
+            })
+        );
+        assert_eq!(aapl.longest_zero_volume_run, 2);
+        assert_eq!(
+            aapl.jumps,
+            vec![Jump {
+                timestamp: Utc.ymd(2021, 1, 4).and_hms(15, 30, 0),
+                from: Decimal::new(100, 0),
+                to: Decimal::new(120, 0),
+                change: Decimal::new(2, 1),
+            }]
+        );
+        assert_eq!(aapl.breaches.len(), 3);
+    }
+}
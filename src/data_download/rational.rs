@@ -0,0 +1,53 @@
+use anyhow::{anyhow, Result};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::ToPrimitive;
+use rust_decimal::Decimal;
+
+/// Largest mantissa a `Decimal` can hold, 2^96 - 1.
+const MAX_MANTISSA: i128 = 79_228_162_514_264_337_593_543_950_335;
+
+/// Maximum number of decimal places of a `Decimal`.
+const MAX_SCALE: u32 = 28;
+
+pub fn to_rational(d: Decimal) -> BigRational {
+    BigRational::new(BigInt::from(d.mantissa()), BigInt::from(10).pow(d.scale()))
+}
+
+/// Round `r` to the most decimal places that fit in a `Decimal`.
+pub fn to_decimal(r: &BigRational) -> Result<Decimal> {
+    for scale in (0..=MAX_SCALE).rev() {
+        let scaled = (r * BigInt::from(10).pow(scale)).round().to_integer();
+        if let Some(mantissa) = scaled.to_i128() {
+            if mantissa.abs() <= MAX_MANTISSA {
+                return Ok(Decimal::from_i128_with_scale(mantissa, scale).normalize());
+            }
+        }
+    }
+    Err(anyhow!("{} is too large for a decimal", r))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let d = Decimal::new(-12345, 3);
+        assert_eq!(
+            to_rational(d),
+            BigRational::new((-12345).into(), 1000.into())
+        );
+        assert_eq!(to_decimal(&to_rational(d)).unwrap(), d);
+
+        let third = BigRational::new(1.into(), 3.into());
+        assert_eq!(
+            to_decimal(&third).unwrap(),
+            "0.3333333333333333333333333333".parse::<Decimal>().unwrap()
+        );
+        assert_eq!(
+            to_decimal(&(third * BigInt::from(300))).unwrap(),
+            Decimal::new(100, 0)
+        );
+    }
+}
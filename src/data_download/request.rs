@@ -0,0 +1,229 @@
+use crate::settings::RequestSettings;
+use anyhow::{anyhow, Result};
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::error::Error;
+use std::fmt::Display;
+use std::future::Future;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{sleep, sleep_until, Duration, Instant};
+use tracing::{error, warn};
+
+/// A ticker that could not be fetched, even after retrying.
+#[derive(Debug, Clone, Serialize)]
+pub struct FetchFailure {
+    pub ticker: String,
+    pub dataset: &'static str,
+    pub error: String,
+}
+
+/// Error out if any ticker failed and `fail_on_error` is set, otherwise log the failures.
+pub fn check_failures(failures: &[FetchFailure], fail_on_error: bool) -> Result<()> {
+    if failures.is_empty() {
+        return Ok(());
+    }
+    let summary: Vec<String> = failures
+        .iter()
+        .map(|f| format!("{} {} ({})", f.ticker, f.dataset, f.error))
+        .collect();
+    if fail_on_error {
+        Err(anyhow!("Failed to download: {}", summary.join(", ")))
+    } else {
+        error!(
+            "Continuing with incomplete data. Failed to download: {}",
+            summary.join(", ")
+        );
+        Ok(())
+    }
+}
+
+/// Error of a REST client, which can tell whether the request is worth retrying.
+pub trait RequestError: Display {
+    /// Timeouts, dropped connections, rate limiting and server errors. Anything else, e.g. an
+    /// unknown ticker or a bad API key, fails the same way when retried.
+    fn is_transient(&self) -> bool;
+}
+
+/// Whether `error` was caused by a transient HTTP failure, judged by the first `reqwest::Error`
+/// among its sources.
+pub fn is_transient_http(error: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+            return error.is_timeout()
+                || error.is_connect()
+                || matches!(
+                    error.status(),
+                    Some(status) if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+                );
+        }
+        source = error.source();
+    }
+    false
+}
+
+/// Limits the number of in-flight requests and the rate at which new ones start.
+struct RateLimiter {
+    permits: Semaphore,
+    interval: Option<Duration>,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(max_concurrent: usize, requests_per_second: u32) -> Self {
+        let interval = if requests_per_second == 0 {
+            None
+        } else {
+            Some(Duration::from_secs(1) / requests_per_second)
+        };
+        Self {
+            permits: Semaphore::new(max_concurrent.max(1)),
+            interval,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn run<Fut: Future>(&self, fut: Fut) -> Fut::Output {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .expect("Semaphore is never closed");
+        if let Some(interval) = self.interval {
+            let slot = {
+                let mut next_slot = self.next_slot.lock().await;
+                let slot = (*next_slot).max(Instant::now());
+                *next_slot = slot + interval;
+                slot
+            };
+            sleep_until(slot).await;
+        }
+        fut.await
+    }
+}
+
+/// Shared request layer for a REST client, adding rate limiting and retries with exponential
+/// backoff.
+pub struct Requester {
+    limiter: RateLimiter,
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Requester {
+    pub fn new(settings: &RequestSettings) -> Self {
+        Self {
+            limiter: RateLimiter::new(settings.max_concurrent, settings.requests_per_second),
+            max_retries: settings.max_retries,
+            initial_backoff: Duration::from_millis(settings.initial_backoff_ms),
+            max_backoff: Duration::from_millis(settings.max_backoff_ms),
+        }
+    }
+
+    /// Send the request built by `request` until it succeeds, fails with an error that isn't
+    /// transient or retries are exhausted.
+    pub async fn send<F, Fut, T, E>(&self, description: String, request: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: RequestError,
+    {
+        let mut backoff = self.initial_backoff;
+        let mut attempt = 0;
+        loop {
+            match self.limiter.run(request()).await {
+                Ok(response) => return Ok(response),
+                Err(e) if attempt < self.max_retries && e.is_transient() => {
+                    attempt += 1;
+                    warn!(
+                        "Request for {} failed, retrying in {:?} ({}/{}). Error: {}",
+                        description, backoff, attempt, self.max_retries, e
+                    );
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                }
+                Err(e) => {
+                    return Err(anyhow!(
+                        "Request for {} failed after {} attempts. Error: {}",
+                        description,
+                        attempt + 1,
+                        e
+                    ))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fmt;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Debug)]
+    struct TestError {
+        transient: bool,
+    }
+
+    impl Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "transient: {}", self.transient)
+        }
+    }
+
+    impl RequestError for TestError {
+        fn is_transient(&self) -> bool {
+            self.transient
+        }
+    }
+
+    fn settings(max_retries: u32) -> RequestSettings {
+        RequestSettings {
+            max_retries,
+            initial_backoff_ms: 1,
+            max_backoff_ms: 2,
+            ..RequestSettings::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let attempts = AtomicU32::new(0);
+        let request = || async {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                Err(TestError { transient: true })
+            } else {
+                Ok(1)
+            }
+        };
+        let res = Requester::new(&settings(2))
+            .send("test".to_string(), request)
+            .await;
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        attempts.store(0, Ordering::SeqCst);
+        let res = Requester::new(&settings(1))
+            .send("test".to_string(), request)
+            .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // Client errors aren't retried
+        attempts.store(0, Ordering::SeqCst);
+        let request = || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<u32, _>(TestError { transient: false })
+        };
+        let res = Requester::new(&settings(5))
+            .send("test".to_string(), request)
+            .await;
+        assert!(res.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+
+        let invalid_url = reqwest::Client::new().get("not a url").build().unwrap_err();
+        assert!(!is_transient_http(&invalid_url));
+    }
+}
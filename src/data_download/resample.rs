@@ -0,0 +1,177 @@
+use crate::calendar::{extended_hours, regular_session, trading_date};
+use crate::data_download::{read_price_file, Bar, BarSize, PriceData, Session};
+use crate::settings::BarTimespan;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use rust_decimal::prelude::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Start of the part of the trading day `session` covers on `date`.
+fn session_start(date: NaiveDate, session: Session) -> DateTime<Utc> {
+    match session {
+        Session::Pre => extended_hours(date).0,
+        Session::Regular => regular_session(date).0,
+        Session::Post => regular_session(date).1,
+    }
+}
+
+/// Combine consecutive bars of the same session into one.
+fn aggregate(timestamp: DateTime<Utc>, bars: &[Bar]) -> Bar {
+    let first = &bars[0];
+    let volume: Decimal = bars.iter().map(|bar| bar.volume).sum();
+    let vwap = if volume.is_zero() {
+        None
+    } else {
+        bars.iter()
+            .map(|bar| bar.vwap.map(|vwap| vwap * bar.volume))
+            .sum::<Option<Decimal>>()
+            .map(|notional| notional / volume)
+    };
+    Bar {
+        timestamp,
+        open: first.open,
+        high: bars.iter().map(|bar| bar.high).max().unwrap_or(first.high),
+        low: bars.iter().map(|bar| bar.low).min().unwrap_or(first.low),
+        close: bars[bars.len() - 1].close,
+        volume,
+        vwap,
+        transactions: bars.iter().map(|bar| bar.transactions).sum(),
+        session: first.session,
+    }
+}
+
+fn resample_bars(bars: &[Bar], to: BarSize) -> Vec<Bar> {
+    let length = to.duration().num_seconds();
+    let mut buckets: BTreeMap<DateTime<Utc>, Vec<Bar>> = BTreeMap::new();
+    for bar in bars {
+        let date = trading_date(&bar.timestamp);
+        let start = session_start(date, bar.session);
+        let bucket = match to.timespan {
+            BarTimespan::Day => start,
+            _ => {
+                let index = (bar.timestamp - start).num_seconds().div_euclid(length);
+                start + Duration::seconds(index * length)
+            }
+        };
+        buckets.entry(bucket).or_default().push(bar.clone());
+    }
+    buckets
+        .into_iter()
+        .map(|(timestamp, bars)| aggregate(timestamp, &bars))
+        .collect()
+}
+
+/// Aggregate bars of size `from` into coarser bars of size `to`. Intraday bars are aligned to the
+/// start of their session and daily bars cover one session, so no bar spans sessions. The last
+/// bar of a session may be shorter than `to`, e.g. on half-days.
+pub fn resample(prices: &PriceData, from: BarSize, to: BarSize) -> Result<PriceData> {
+    let (from_length, to_length) = (from.duration(), to.duration());
+    if from_length.num_seconds() == 0 || to_length.num_seconds() % from_length.num_seconds() != 0 {
+        return Err(anyhow!(
+            "Can't resample {:?} bars to {:?}, which isn't a multiple",
+            from,
+            to
+        ));
+    }
+    if matches!(to.timespan, BarTimespan::Day) && to.multiplier != 1 {
+        return Err(anyhow!("Bars longer than a day would span sessions"));
+    }
+    Ok(prices
+        .iter()
+        .map(|(ticker, bars)| (ticker.clone(), resample_bars(bars, to)))
+        .collect())
+}
+
+/// Read a file written by `download_data` with bars of `bar_size`, aggregated to `to` if set.
+/// Returns the prices with the size of their bars.
+pub fn read_resampled_price_file<T: AsRef<Path>>(
+    path: T,
+    bar_size: BarSize,
+    to: Option<BarSize>,
+) -> Result<(PriceData, BarSize)> {
+    let prices = read_price_file(path)?;
+    match to {
+        Some(to) => Ok((resample(&prices, bar_size, to)?, to)),
+        None => Ok((prices, bar_size)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn bar(timestamp: DateTime<Utc>, price: i64, volume: i64, session: Session) -> Bar {
+        Bar {
+            timestamp,
+            open: Decimal::new(price, 0),
+            high: Decimal::new(price + 1, 0),
+            low: Decimal::new(price - 1, 0),
+            close: Decimal::new(price, 0),
+            volume: Decimal::new(volume, 0),
+            vwap: Some(Decimal::new(price, 0)),
+            transactions: Some(1),
+            session,
+        }
+    }
+
+    #[test]
+    fn test_resample() {
+        let five = BarSize {
+            multiplier: 5,
+            timespan: BarTimespan::Minute,
+        };
+        let fifteen = BarSize {
+            multiplier: 15,
+            timespan: BarTimespan::Minute,
+        };
+        let day = BarSize {
+            multiplier: 1,
+            timespan: BarTimespan::Day,
+        };
+        // Half-day, closing at 18:00 UTC
+        let t = |hour, minute| Utc.ymd(2021, 11, 26).and_hms(hour, minute, 0);
+        let bars = vec![
+            bar(t(14, 25), 90, 10, Session::Pre),
+            bar(t(14, 30), 100, 10, Session::Regular),
+            bar(t(14, 35), 102, 30, Session::Regular),
+            bar(t(14, 40), 101, 10, Session::Regular),
+            bar(t(14, 45), 103, 10, Session::Regular),
+            bar(t(17, 55), 104, 10, Session::Regular),
+            bar(t(18, 0), 110, 10, Session::Post),
+        ];
+        let mut prices = PriceData::new();
+        prices.insert("AAPL".to_string(), bars);
+
+        let resampled = &resample(&prices, five, fifteen).unwrap()["AAPL"];
+        let summary: Vec<(DateTime<Utc>, Session)> =
+            resampled.iter().map(|b| (b.timestamp, b.session)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (t(14, 15), Session::Pre),
+                (t(14, 30), Session::Regular),
+                (t(14, 45), Session::Regular),
+                (t(17, 45), Session::Regular),
+                (t(18, 0), Session::Post),
+            ]
+        );
+        let first = &resampled[1];
+        assert_eq!(first.open, Decimal::new(100, 0));
+        assert_eq!(first.high, Decimal::new(103, 0));
+        assert_eq!(first.low, Decimal::new(99, 0));
+        assert_eq!(first.close, Decimal::new(101, 0));
+        assert_eq!(first.volume, Decimal::new(50, 0));
+        assert_eq!(first.vwap, Some(Decimal::new(10140, 2)));
+        assert_eq!(first.transactions, Some(3));
+
+        let daily = &resample(&prices, five, day).unwrap()["AAPL"];
+        assert_eq!(daily.len(), 3);
+        assert_eq!(daily[1].timestamp, t(14, 30));
+        assert_eq!(daily[1].close, Decimal::new(104, 0));
+        assert_eq!(daily[2].timestamp, t(18, 0));
+
+        assert!(resample(&prices, fifteen, five).is_err());
+    }
+}
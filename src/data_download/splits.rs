@@ -1,38 +1,95 @@
+use crate::data_download::rational::to_rational;
+use crate::data_download::request::FetchFailure;
+use crate::market_data::CorporateActionSource;
+use crate::settings::CorporateActionRange;
+use anyhow::{anyhow, Result};
 use chrono::NaiveDate;
-use iex::{client::Client, splits::GetSplits, Range};
+use futures::future::join_all;
+use num_rational::BigRational;
 use rust_decimal::prelude::*;
+use serde::Serialize;
 use std::collections::HashMap;
 use tracing::error;
 
-pub type SplitData = HashMap<String, Vec<(NaiveDate, Decimal)>>;
+/// `from` shares becoming `to` shares, e.g. 1 into 4 for a 4-for-1 split or 10 into 1 for a
+/// 1-for-10 reverse split.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SplitRatio {
+    pub from: Decimal,
+    pub to: Decimal,
+}
+
+impl SplitRatio {
+    pub fn new(from: Decimal, to: Decimal) -> Result<Self> {
+        if from <= Decimal::ZERO || to <= Decimal::ZERO {
+            return Err(anyhow!("Invalid split of {} into {} shares", from, to));
+        }
+        Ok(Self { from, to })
+    }
+
+    /// A split known only by its price ratio `from / to`.
+    pub fn from_price_ratio(ratio: Decimal) -> Result<Self> {
+        Self::new(ratio, Decimal::ONE)
+    }
+
+    /// Whether shares are consolidated, raising the price.
+    pub fn is_reverse(&self) -> bool {
+        self.to < self.from
+    }
+
+    /// Exact factor applied to prices before the ex-date.
+    pub fn price_factor(&self) -> BigRational {
+        to_rational(self.from) / to_rational(self.to)
+    }
+}
+
+pub type SplitData = HashMap<String, Vec<(NaiveDate, SplitRatio)>>;
 
 pub async fn download_splits<T: AsRef<str> + std::fmt::Display>(
-    client: &Client<'_>,
+    source: &dyn CorporateActionSource,
     tickers: &[T],
-) -> SplitData {
-    let queries = tickers.iter().map(|ticker| GetSplits {
-        symbol: ticker.as_ref(),
-        range: Range::ThreeMonths,
-    });
+    range: CorporateActionRange,
+) -> (SplitData, Vec<FetchFailure>) {
     tracing::debug!("Downloading splits data");
-    client
-        .send_all(queries)
+    let futs = tickers
+        .iter()
+        .map(|ticker| source.splits(ticker.as_ref(), range));
+    let mut failures = Vec::new();
+    let data = join_all(futs)
         .await
         .into_iter()
         .zip(tickers)
-        .map(|(res, ticker)| match res {
-            Ok(v) if v.is_empty() => (ticker.to_string(), vec![]),
-            Ok(splits) => {
-                let data: Vec<(NaiveDate, Decimal)> = splits
-                    .iter()
-                    .map(|split| (split.ex_date, Decimal::from_f64(split.ratio).unwrap()))
-                    .collect();
-                (ticker.to_string(), data)
-            }
+        .filter_map(|(res, ticker)| match res {
+            Ok(data) => Some((ticker.to_string(), data)),
             Err(e) => {
                 error!("Failed to download splits for {}. Error: {}", ticker, e);
-                (ticker.to_string(), vec![])
+                failures.push(FetchFailure {
+                    ticker: ticker.to_string(),
+                    dataset: "splits",
+                    error: e.to_string(),
+                });
+                None
             }
         })
-        .collect()
+        .collect();
+    (data, failures)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_ratio() {
+        let split = SplitRatio::new(Decimal::ONE, Decimal::new(3, 0)).unwrap();
+        assert!(!split.is_reverse());
+        assert_eq!(split.price_factor(), BigRational::new(1.into(), 3.into()));
+
+        let reverse = SplitRatio::new(Decimal::new(10, 0), Decimal::ONE).unwrap();
+        assert!(reverse.is_reverse());
+        assert_eq!(reverse.price_factor(), BigRational::from_integer(10.into()));
+
+        assert!(SplitRatio::new(Decimal::ZERO, Decimal::ONE).is_err());
+        assert!(SplitRatio::from_price_ratio(Decimal::new(-5, 1)).is_err());
+    }
 }
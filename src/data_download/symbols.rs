@@ -0,0 +1,297 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum SymbolEvent {
+    Rename,
+    Delisted,
+    Suspended,
+}
+
+/// A row of the symbol change file: `ticker,date,event,new_ticker`. For renames, `date` is the
+/// first day traded as `new_ticker`. For delistings and suspensions it is the first day without
+/// trading.
+#[derive(Debug, Deserialize)]
+struct SymbolChangeRow {
+    ticker: String,
+    date: NaiveDate,
+    event: SymbolEvent,
+    new_ticker: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Rename {
+    from: String,
+    to: String,
+    date: NaiveDate,
+}
+
+/// Dates over which a ticker traded under `symbol`. Open ends reach past the price window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SymbolSegment {
+    pub symbol: String,
+    pub from: Option<NaiveDate>,
+    pub until: Option<NaiveDate>,
+}
+
+impl SymbolSegment {
+    fn contains(&self, date: NaiveDate) -> bool {
+        !matches!(self.from, Some(from) if date < from)
+            && !matches!(self.until, Some(until) if date > until)
+    }
+}
+
+/// Why a requested ticker has no data from `date` onwards.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TickerStatus {
+    Delisted { date: NaiveDate },
+    Suspended { date: NaiveDate },
+    Renamed { date: NaiveDate, to: String },
+}
+
+impl fmt::Display for TickerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TickerStatus::Delisted { date } => write!(f, "delisted on {}", date),
+            TickerStatus::Suspended { date } => write!(f, "suspended on {}", date),
+            TickerStatus::Renamed { date, to } => write!(f, "renamed to {} on {}", to, date),
+        }
+    }
+}
+
+/// A requested ticker whose history spans several symbols or that stopped trading.
+#[derive(Debug, Clone, Serialize)]
+pub struct SymbolChange {
+    pub ticker: String,
+    pub history: Vec<SymbolSegment>,
+    pub status: Option<TickerStatus>,
+}
+
+/// Renames, delistings and suspensions, used to stitch histories across symbol changes.
+#[derive(Debug, Default)]
+pub struct SymbolMap {
+    renames: Vec<Rename>,
+    statuses: HashMap<String, Vec<TickerStatus>>,
+}
+
+impl SymbolMap {
+    pub fn read<T: AsRef<Path>>(path: T) -> Result<Self> {
+        let mut map = SymbolMap::default();
+        for row in csv::Reader::from_path(path)?.deserialize() {
+            let row: SymbolChangeRow = row?;
+            let status = match row.event {
+                SymbolEvent::Rename => {
+                    let (ticker, date) = (&row.ticker, row.date);
+                    let to = row.new_ticker.ok_or_else(|| {
+                        anyhow!("Rename of {} on {} has no new ticker", ticker, date)
+                    })?;
+                    map.renames.push(Rename {
+                        from: row.ticker.clone(),
+                        to: to.clone(),
+                        date: row.date,
+                    });
+                    TickerStatus::Renamed { date: row.date, to }
+                }
+                SymbolEvent::Delisted => TickerStatus::Delisted { date: row.date },
+                SymbolEvent::Suspended => TickerStatus::Suspended { date: row.date },
+            };
+            map.statuses.entry(row.ticker).or_default().push(status);
+        }
+        Ok(map)
+    }
+
+    /// Symbols `ticker` traded under since `start_date`, oldest first.
+    pub fn history(&self, ticker: &str, start_date: NaiveDate) -> Vec<SymbolSegment> {
+        let mut history = Vec::new();
+        let mut symbol = ticker.to_string();
+        let mut until: Option<NaiveDate> = None;
+        // Bounded by the number of renames in case the file contains a cycle
+        for _ in 0..=self.renames.len() {
+            let previous = self
+                .renames
+                .iter()
+                .filter(|r| r.to == symbol && r.date > start_date)
+                .filter(|r| !matches!(until, Some(until) if r.date > until))
+                .max_by_key(|r| r.date);
+            match previous {
+                Some(rename) => {
+                    history.push(SymbolSegment {
+                        symbol: std::mem::replace(&mut symbol, rename.from.clone()),
+                        from: Some(rename.date),
+                        until,
+                    });
+                    until = Some(rename.date - Duration::days(1));
+                }
+                None => break,
+            }
+        }
+        history.push(SymbolSegment {
+            symbol,
+            from: None,
+            until,
+        });
+        history.reverse();
+        history
+    }
+
+    /// The latest delisting, suspension or rename of `ticker` on or after `start_date`.
+    pub fn status(&self, ticker: &str, start_date: NaiveDate) -> Option<TickerStatus> {
+        let date = |status: &TickerStatus| match status {
+            TickerStatus::Delisted { date }
+            | TickerStatus::Suspended { date }
+            | TickerStatus::Renamed { date, .. } => *date,
+        };
+        self.statuses
+            .get(ticker)?
+            .iter()
+            .filter(|status| date(status) >= start_date)
+            .max_by_key(|status| date(status))
+            .cloned()
+    }
+
+    /// History and status of every ticker in `tickers` affected by a symbol change.
+    pub fn changes<T: AsRef<str>>(
+        &self,
+        tickers: &[T],
+        start_date: NaiveDate,
+    ) -> Vec<SymbolChange> {
+        tickers
+            .iter()
+            .map(|ticker| SymbolChange {
+                ticker: ticker.as_ref().to_string(),
+                history: self.history(ticker.as_ref(), start_date),
+                status: self.status(ticker.as_ref(), start_date),
+            })
+            .filter(|change| change.history.len() > 1 || change.status.is_some())
+            .collect()
+    }
+}
+
+/// Every symbol that needs to be fetched to cover `histories`.
+pub fn history_symbols(histories: &HashMap<String, Vec<SymbolSegment>>) -> Vec<String> {
+    let mut symbols: Vec<String> = histories
+        .values()
+        .flat_map(|history| history.iter().map(|segment| segment.symbol.clone()))
+        .collect();
+    symbols.sort();
+    symbols.dedup();
+    symbols
+}
+
+/// Collect each ticker's entries from the symbols in its history, keeping only those dated within
+/// the segment traded under that symbol. Tickers without data under any of their symbols are left
+/// out.
+pub fn stitch<V: Clone, F: Fn(&V) -> NaiveDate>(
+    data: &HashMap<String, Vec<V>>,
+    histories: &HashMap<String, Vec<SymbolSegment>>,
+    date: F,
+) -> HashMap<String, Vec<V>> {
+    histories
+        .iter()
+        .filter_map(|(ticker, history)| {
+            let mut stitched = Vec::new();
+            let mut found = false;
+            for segment in history {
+                if let Some(entries) = data.get(&segment.symbol) {
+                    found = true;
+                    stitched.extend(
+                        entries
+                            .iter()
+                            .filter(|entry| segment.contains(date(entry)))
+                            .cloned(),
+                    );
+                }
+            }
+            if found {
+                Some((ticker.clone(), stitched))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::write;
+
+    #[test]
+    fn test_symbol_map() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("symbol_changes.csv");
+        write(
+            &path,
+            "ticker,date,event,new_ticker\n\
+             FB,2022-06-09,rename,META\n\
+             FBOLD,2021-01-04,rename,FB\n\
+             TWTR,2022-11-08,delisted,\n",
+        )
+        .unwrap();
+        let map = SymbolMap::read(&path).unwrap();
+        let start_date = NaiveDate::from_ymd(2021, 6, 1);
+
+        let history = map.history("META", start_date);
+        assert_eq!(
+            history,
+            vec![
+                SymbolSegment {
+                    symbol: "FB".to_string(),
+                    from: None,
+                    until: Some(NaiveDate::from_ymd(2022, 6, 8)),
+                },
+                SymbolSegment {
+                    symbol: "META".to_string(),
+                    from: Some(NaiveDate::from_ymd(2022, 6, 9)),
+                    until: None,
+                },
+            ]
+        );
+        assert_eq!(
+            map.status("TWTR", start_date),
+            Some(TickerStatus::Delisted {
+                date: NaiveDate::from_ymd(2022, 11, 8)
+            })
+        );
+        assert_eq!(
+            map.status("FB", start_date),
+            Some(TickerStatus::Renamed {
+                date: NaiveDate::from_ymd(2022, 6, 9),
+                to: "META".to_string()
+            })
+        );
+        assert_eq!(map.changes(&["AAPL", "META", "TWTR"], start_date).len(), 2);
+
+        let histories: HashMap<String, Vec<SymbolSegment>> =
+            vec![("META".to_string(), history)].into_iter().collect();
+        assert_eq!(history_symbols(&histories), vec!["FB", "META"]);
+        let data: HashMap<String, Vec<NaiveDate>> = vec![
+            (
+                "FB".to_string(),
+                vec![
+                    NaiveDate::from_ymd(2022, 6, 8),
+                    // A different company reusing the old symbol
+                    NaiveDate::from_ymd(2022, 6, 10),
+                ],
+            ),
+            ("META".to_string(), vec![NaiveDate::from_ymd(2022, 6, 9)]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            stitch(&data, &histories, |date| *date)["META"],
+            vec![
+                NaiveDate::from_ymd(2022, 6, 8),
+                NaiveDate::from_ymd(2022, 6, 9)
+            ]
+        );
+
+        dir.close().unwrap();
+    }
+}
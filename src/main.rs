@@ -1,14 +1,23 @@
 use anyhow::Result;
 
+mod backtest;
+mod calendar;
+mod calibration;
 mod data_download;
+mod market_data;
 mod settings;
 mod trading;
-use data_download::download_data;
+mod universe;
+use backtest::backtest;
+use calibration::calibrate;
+use data_download::{download_data, invalidate_cache, read_resampled_price_file};
+use market_data::{open_close_source, Sources};
 use settings::{RunMode, Settings};
 use std::fs::File;
 use tracing::subscriber::set_global_default;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 use trading::run;
+use universe::Universe;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,12 +28,82 @@ async fn main() -> Result<()> {
         .finish();
     set_global_default(subscriber)?;
     let settings = Settings::new()?;
+    let market_data = &settings.app.market_data;
+    let requests = &settings.app.download.requests;
+    let sources = || Sources::new(market_data, requests);
+    let data_bar_size = settings.app.download.output_bar_size();
+    let universe_settings = &settings.app.universe;
+    let tickers = &settings.app.tickers;
+    let universe = || Universe::load(universe_settings, tickers);
+    // Calibration and trading only restrict their pairs when a universe file is given
+    let universe_file = || {
+        universe_settings
+            .file
+            .as_ref()
+            .map(Universe::read)
+            .transpose()
+    };
     match settings.app.run_mode {
-        RunMode::Download { out_file } => {
-            download_data(&settings.app.tickers, File::create(out_file)?).await?
+        RunMode::Download { out_file, format } => {
+            download_data(
+                &universe()?,
+                &sources()?,
+                &settings.app.download,
+                universe_settings,
+                format,
+                File::create(out_file)?,
+            )
+            .await?
+        }
+        RunMode::InvalidateCache => {
+            invalidate_cache(&universe()?.tickers(), &settings.app.download)?
+        }
+        RunMode::Calibrate {
+            data_file,
+            out_file,
+        } => {
+            let (prices, _) = read_resampled_price_file(
+                data_file,
+                data_bar_size,
+                settings.app.calibration.resample,
+            )?;
+            calibrate(
+                prices,
+                out_file,
+                &settings.app.calibration,
+                universe_file()?.as_ref(),
+            )?
         }
         RunMode::Run { data_file } => {
-            run(settings.app.cash, data_file, settings.kafka).await?;
+            run(
+                settings.app.cash,
+                data_file,
+                universe_file()?.as_ref(),
+                open_close_source(market_data, requests)?.as_ref(),
+                &settings.app.trading,
+                settings.kafka,
+            )
+            .await?;
+        }
+        RunMode::Backtest {
+            data_file,
+            pair_file,
+            out_file,
+        } => {
+            let (prices, bar_size) = read_resampled_price_file(
+                data_file,
+                data_bar_size,
+                settings.app.backtest.resample,
+            )?;
+            backtest(
+                settings.app.cash,
+                prices,
+                bar_size,
+                pair_file,
+                out_file,
+                universe_file()?.as_ref(),
+                &settings.app.trading,
+            )?;
         }
     }
     Ok(())
@@ -0,0 +1,161 @@
+use crate::data_download::{is_transient_http, RequestError, Requester, SplitRatio};
+use crate::market_data::CorporateActionSource;
+use crate::settings::CorporateActionRange;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use futures::future::{BoxFuture, FutureExt};
+use iex::{
+    client::{Client, Error},
+    dividends::GetDividends,
+    splits::GetSplits,
+    Range,
+};
+use rust_decimal::prelude::*;
+
+impl From<CorporateActionRange> for Range {
+    fn from(range: CorporateActionRange) -> Self {
+        match range {
+            CorporateActionRange::OneMonth => Range::OneMonth,
+            CorporateActionRange::ThreeMonths => Range::ThreeMonths,
+            CorporateActionRange::SixMonths => Range::SixMonths,
+            CorporateActionRange::OneYear => Range::OneYear,
+            CorporateActionRange::TwoYears => Range::TwoYears,
+            CorporateActionRange::FiveYears => Range::FiveYears,
+        }
+    }
+}
+
+/// Largest float error tolerated in a share count before it is treated as fractional.
+const SHARE_COUNT_TOLERANCE: f64 = 1e-6;
+
+/// A whole number of shares. IEX sends share counts as JSON numbers, so they are rounded before
+/// building an exact ratio from them. Fractional counts aren't exact and give `None`.
+fn share_count(factor: f64) -> Option<Decimal> {
+    let shares = factor.round();
+    if shares >= 1.0 && (factor - shares).abs() < SHARE_COUNT_TOLERANCE {
+        Decimal::from_f64(shares)
+    } else {
+        None
+    }
+}
+
+/// Exact share counts where IEX provides them, falling back to the price ratio.
+fn split_ratio(from_factor: f64, to_factor: f64, ratio: f64) -> Result<SplitRatio> {
+    match share_count(from_factor).zip(share_count(to_factor)) {
+        Some((from, to)) => SplitRatio::new(from, to),
+        None => Decimal::from_f64(ratio)
+            .ok_or_else(|| anyhow!("Invalid split ratio {}", ratio))
+            .and_then(SplitRatio::from_price_ratio),
+    }
+}
+
+impl RequestError for Error {
+    fn is_transient(&self) -> bool {
+        is_transient_http(self)
+    }
+}
+
+pub struct IexSource {
+    client: Client<'static>,
+    requester: Requester,
+}
+
+impl IexSource {
+    pub fn from_env(requester: Requester) -> Result<Self> {
+        Ok(Self {
+            client: Client::from_env()?,
+            requester,
+        })
+    }
+}
+
+impl CorporateActionSource for IexSource {
+    fn dividends<'a>(
+        &'a self,
+        ticker: &'a str,
+        range: CorporateActionRange,
+    ) -> BoxFuture<'a, Result<Vec<(NaiveDate, Decimal)>>> {
+        async move {
+            let dividends = self
+                .requester
+                .send(format!("dividends of {}", ticker), || {
+                    self.client.send(GetDividends {
+                        symbol: ticker,
+                        range: range.into(),
+                    })
+                })
+                .await?;
+            Ok(dividends
+                .iter()
+                .map(|div| (div.ex_date, div.amount))
+                .collect())
+        }
+        .boxed()
+    }
+
+    fn splits<'a>(
+        &'a self,
+        ticker: &'a str,
+        range: CorporateActionRange,
+    ) -> BoxFuture<'a, Result<Vec<(NaiveDate, SplitRatio)>>> {
+        async move {
+            let splits = self
+                .requester
+                .send(format!("splits of {}", ticker), || {
+                    self.client.send(GetSplits {
+                        symbol: ticker,
+                        range: range.into(),
+                    })
+                })
+                .await?;
+            splits
+                .iter()
+                .map(|split| {
+                    let ratio = split_ratio(split.from_factor, split.to_factor, split.ratio)?;
+                    Ok((split.ex_date, ratio))
+                })
+                .collect()
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use num_rational::BigRational;
+
+    #[test]
+    fn test_split_ratio() {
+        // A 3-for-2 split, with share counts off by float error and an inexact price ratio
+        let split = split_ratio(2.0000000000000004, 2.9999999999999996, 2.0 / 3.0).unwrap();
+        assert_eq!(
+            split,
+            SplitRatio::new(Decimal::new(2, 0), Decimal::new(3, 0)).unwrap()
+        );
+        assert_eq!(split.price_factor(), BigRational::new(2.into(), 3.into()));
+        assert_eq!(
+            split_ratio(0.0, 0.0, 0.5).unwrap(),
+            SplitRatio::from_price_ratio(Decimal::new(5, 1)).unwrap()
+        );
+        // The same split given as 1 for 1.5 falls back to the price ratio
+        let price_ratio = Decimal::from_f64(2.0 / 3.0).unwrap();
+        assert_eq!(
+            split_ratio(1.0, 1.5, 2.0 / 3.0).unwrap(),
+            SplitRatio::from_price_ratio(price_ratio).unwrap()
+        );
+        assert_eq!(
+            split_ratio(0.6, 0.9, 2.0 / 3.0).unwrap(),
+            SplitRatio::from_price_ratio(price_ratio).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_share_count() {
+        assert_eq!(share_count(3.0), Some(Decimal::new(3, 0)));
+        assert_eq!(share_count(2.9999999999999996), Some(Decimal::new(3, 0)));
+        assert_eq!(share_count(1.5), None);
+        assert_eq!(share_count(0.6), None);
+        assert_eq!(share_count(0.0), None);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::calendar::trading_date;
+use crate::data_download::{Bar, BarSize, SplitRatio};
+use crate::market_data::{BarSource, CorporateActionSource, OpenCloseSource};
+use crate::settings::CorporateActionRange;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use futures::future::{ready, BoxFuture, FutureExt};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+struct DividendRow {
+    ticker: String,
+    ex_date: NaiveDate,
+    amount: Decimal,
+}
+
+/// Either the share counts `from` and `to`, or the price ratio `from / to`.
+#[derive(Debug, Deserialize)]
+struct SplitRow {
+    ticker: String,
+    ex_date: NaiveDate,
+    from: Option<Decimal>,
+    to: Option<Decimal>,
+    ratio: Option<Decimal>,
+}
+
+impl SplitRow {
+    fn split_ratio(&self) -> Result<SplitRatio> {
+        match (self.from, self.to, self.ratio) {
+            (Some(from), Some(to), _) => SplitRatio::new(from, to),
+            (_, _, Some(ratio)) => SplitRatio::from_price_ratio(ratio),
+            _ => Err(anyhow!(
+                "Split of {} on {} needs either from and to or ratio",
+                self.ticker,
+                self.ex_date
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenCloseRow {
+    ticker: String,
+    open: Decimal,
+    previous_close: Decimal,
+}
+
+/// Market data read from a directory, for tests and offline runs:
+///
+/// - `bars/{ticker}.json`: a JSON array of unadjusted bars, in the same format as the price cache
+/// - `dividends.csv`: `ticker,ex_date,amount`
+/// - `splits.csv`: `ticker,ex_date,from,to,ratio`, with either the share counts or the price ratio
+/// - `open_close.csv`: `ticker,open,previous_close`
+///
+/// Missing corporate-action files are treated as having no corporate actions. Bars are returned
+/// as stored, whatever the requested bar size.
+pub struct LocalSource {
+    dir: PathBuf,
+}
+
+impl LocalSource {
+    pub fn new<T: AsRef<Path>>(dir: T) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn read_bars(
+        &self,
+        ticker: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+    ) -> Result<Vec<Bar>> {
+        let path = self.dir.join("bars").join(format!("{}.json", ticker));
+        let file = File::open(&path)
+            .map_err(|e| anyhow!("Failed to open {}. Error: {}", path.display(), e))?;
+        let bars: Vec<Bar> = serde_json::from_reader(file)?;
+        Ok(bars
+            .into_iter()
+            .filter(|bar| {
+                let date = trading_date(&bar.timestamp);
+                date >= start_date && date <= end_date
+            })
+            .collect())
+    }
+
+    /// Rows of `file_name` for `ticker`, or none if the file doesn't exist.
+    fn read_rows<R, F>(&self, file_name: &str, ticker: &str, row_ticker: F) -> Result<Vec<R>>
+    where
+        R: for<'de> Deserialize<'de>,
+        F: Fn(&R) -> &str,
+    {
+        let path = self.dir.join(file_name);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let mut rows = Vec::new();
+        for row in csv::Reader::from_path(path)?.deserialize() {
+            let row: R = row?;
+            if row_ticker(&row) == ticker {
+                rows.push(row);
+            }
+        }
+        Ok(rows)
+    }
+
+    fn read_dividends(&self, ticker: &str) -> Result<Vec<(NaiveDate, Decimal)>> {
+        let rows = self.read_rows("dividends.csv", ticker, |row: &DividendRow| {
+            row.ticker.as_str()
+        })?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.ex_date, row.amount))
+            .collect())
+    }
+
+    fn read_splits(&self, ticker: &str) -> Result<Vec<(NaiveDate, SplitRatio)>> {
+        let rows = self.read_rows("splits.csv", ticker, |row: &SplitRow| row.ticker.as_str())?;
+        rows.iter()
+            .map(|row| Ok((row.ex_date, row.split_ratio()?)))
+            .collect()
+    }
+
+    fn read_open_close(&self, tickers: &[String]) -> Result<HashMap<String, (Decimal, Decimal)>> {
+        let mut open_close = HashMap::new();
+        for row in csv::Reader::from_path(self.dir.join("open_close.csv"))?.deserialize() {
+            let row: OpenCloseRow = row?;
+            if tickers.contains(&row.ticker) {
+                open_close.insert(row.ticker, (row.open, row.previous_close));
+            }
+        }
+        Ok(open_close)
+    }
+}
+
+impl BarSource for LocalSource {
+    fn bars<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        _bar_size: BarSize,
+    ) -> BoxFuture<'a, Result<Vec<Bar>>> {
+        ready(self.read_bars(ticker, start_date, end_date)).boxed()
+    }
+}
+
+impl CorporateActionSource for LocalSource {
+    fn dividends<'a>(
+        &'a self,
+        ticker: &'a str,
+        _range: CorporateActionRange,
+    ) -> BoxFuture<'a, Result<Vec<(NaiveDate, Decimal)>>> {
+        ready(self.read_dividends(ticker)).boxed()
+    }
+
+    fn splits<'a>(
+        &'a self,
+        ticker: &'a str,
+        _range: CorporateActionRange,
+    ) -> BoxFuture<'a, Result<Vec<(NaiveDate, SplitRatio)>>> {
+        ready(self.read_splits(ticker)).boxed()
+    }
+}
+
+impl OpenCloseSource for LocalSource {
+    fn open_close<'a>(
+        &'a self,
+        tickers: &'a [String],
+    ) -> BoxFuture<'a, Result<HashMap<String, (Decimal, Decimal)>>> {
+        ready(self.read_open_close(tickers)).boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_download::Session;
+    use crate::settings::BarTimespan;
+    use chrono::{TimeZone, Utc};
+    use std::fs::{create_dir_all, write};
+
+    #[tokio::test]
+    async fn test_local_source() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        create_dir_all(dir.join("bars")).unwrap();
+        let bar = |day| Bar {
+            timestamp: Utc.ymd(2021, 1, day).and_hms(14, 30, 0),
+            open: Decimal::new(100, 0),
+            high: Decimal::new(100, 0),
+            low: Decimal::new(100, 0),
+            close: Decimal::new(100, 0),
+            volume: Decimal::new(1000, 0),
+            vwap: None,
+            transactions: None,
+            session: Session::Regular,
+        };
+        serde_json::to_writer(
+            File::create(dir.join("bars").join("AAPL.json")).unwrap(),
+            &vec![bar(4), bar(5), bar(6)],
+        )
+        .unwrap();
+        write(
+            dir.join("splits.csv"),
+            "ticker,ex_date,from,to,ratio\nAAPL,2021-01-05,1,4,\nMSFT,2021-01-05,,,0.5\n",
+        )
+        .unwrap();
+        write(
+            dir.join("open_close.csv"),
+            "ticker,open,previous_close\nAAPL,101,100\nMSFT,201,200\n",
+        )
+        .unwrap();
+
+        let source = LocalSource::new(dir);
+        let bar_size = BarSize {
+            multiplier: 5,
+            timespan: BarTimespan::Minute,
+        };
+        let bars = source
+            .bars(
+                "AAPL",
+                NaiveDate::from_ymd(2021, 1, 5),
+                NaiveDate::from_ymd(2021, 1, 6),
+                bar_size,
+            )
+            .await
+            .unwrap();
+        assert_eq!(bars, vec![bar(5), bar(6)]);
+        assert!(source
+            .bars(
+                "MSFT",
+                NaiveDate::from_ymd(2021, 1, 5),
+                NaiveDate::from_ymd(2021, 1, 6),
+                bar_size,
+            )
+            .await
+            .is_err());
+        assert_eq!(
+            source
+                .splits("AAPL", CorporateActionRange::OneYear)
+                .await
+                .unwrap(),
+            vec![(
+                NaiveDate::from_ymd(2021, 1, 5),
+                SplitRatio::new(Decimal::ONE, Decimal::new(4, 0)).unwrap()
+            )]
+        );
+        assert_eq!(
+            source
+                .splits("MSFT", CorporateActionRange::OneYear)
+                .await
+                .unwrap(),
+            vec![(
+                NaiveDate::from_ymd(2021, 1, 5),
+                SplitRatio::from_price_ratio(Decimal::new(5, 1)).unwrap()
+            )]
+        );
+        assert!(source
+            .dividends("AAPL", CorporateActionRange::OneYear)
+            .await
+            .unwrap()
+            .is_empty());
+        let open_close = source.open_close(&["AAPL".to_string()]).await.unwrap();
+        assert_eq!(
+            open_close["AAPL"],
+            (Decimal::new(101, 0), Decimal::new(100, 0))
+        );
+        assert_eq!(open_close.len(), 1);
+
+        temp_dir.close().unwrap();
+    }
+}
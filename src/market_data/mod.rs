@@ -0,0 +1,103 @@
+use crate::data_download::{Bar, BarSize, Requester, SplitRatio};
+use crate::settings::{
+    CorporateActionRange, MarketDataSettings, MarketDataSource, RequestSettings,
+};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use futures::future::BoxFuture;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+mod iex;
+mod local;
+mod polygon;
+pub use self::iex::IexSource;
+pub use self::local::LocalSource;
+pub use self::polygon::PolygonSource;
+
+/// Intraday bar history.
+pub trait BarSource: Send + Sync {
+    /// Unadjusted bars of `ticker` for every trading day from `start_date` to `end_date`
+    /// inclusive.
+    fn bars<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        bar_size: BarSize,
+    ) -> BoxFuture<'a, Result<Vec<Bar>>>;
+}
+
+/// Dividends and splits by ex-date.
+pub trait CorporateActionSource: Send + Sync {
+    /// Cash amount of each dividend.
+    fn dividends<'a>(
+        &'a self,
+        ticker: &'a str,
+        range: CorporateActionRange,
+    ) -> BoxFuture<'a, Result<Vec<(NaiveDate, Decimal)>>>;
+
+    /// Share counts before and after each split.
+    fn splits<'a>(
+        &'a self,
+        ticker: &'a str,
+        range: CorporateActionRange,
+    ) -> BoxFuture<'a, Result<Vec<(NaiveDate, SplitRatio)>>>;
+}
+
+/// Today's open and the previous close.
+pub trait OpenCloseSource: Send + Sync {
+    /// `(open, previous_close)` per ticker. Tickers without data are left out.
+    fn open_close<'a>(
+        &'a self,
+        tickers: &'a [String],
+    ) -> BoxFuture<'a, Result<HashMap<String, (Decimal, Decimal)>>>;
+}
+
+/// The configured sources of bars and corporate actions for downloads.
+pub struct Sources {
+    pub bars: Box<dyn BarSource>,
+    pub corporate_actions: Box<dyn CorporateActionSource>,
+}
+
+impl Sources {
+    pub fn new(settings: &MarketDataSettings, requests: &RequestSettings) -> Result<Self> {
+        match settings.source {
+            MarketDataSource::Api => Ok(Self {
+                bars: Box::new(PolygonSource::from_env(Arc::new(Requester::new(requests)))?),
+                corporate_actions: Box::new(IexSource::from_env(Requester::new(requests))?),
+            }),
+            MarketDataSource::Local => {
+                let dir = settings
+                    .local_dir
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("No local market data directory configured"))?;
+                Ok(Self {
+                    bars: Box::new(LocalSource::new(dir)),
+                    corporate_actions: Box::new(LocalSource::new(dir)),
+                })
+            }
+        }
+    }
+}
+
+/// The configured source of opens and closes for trading, which needs no credentials for the
+/// download sources.
+pub fn open_close_source(
+    settings: &MarketDataSettings,
+    requests: &RequestSettings,
+) -> Result<Box<dyn OpenCloseSource>> {
+    match settings.source {
+        MarketDataSource::Api => Ok(Box::new(PolygonSource::from_env(Arc::new(
+            Requester::new(requests),
+        ))?)),
+        MarketDataSource::Local => {
+            let dir = settings
+                .local_dir
+                .as_ref()
+                .ok_or_else(|| anyhow!("No local market data directory configured"))?;
+            Ok(Box::new(LocalSource::new(dir)))
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::calendar::trading_date;
+use crate::data_download::{
+    check_failures, is_transient_http, Bar, BarSize, FetchFailure, RequestError, Requester, Session,
+};
+use crate::market_data::{BarSource, OpenCloseSource};
+use crate::settings::BarTimespan;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use futures::future::{join_all, BoxFuture, FutureExt};
+use polygon::rest::{Aggregate, Client, Error, GetAggregate, GetTickerSnapshot, Timespan};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Maximum number of bars Polygon returns for a single aggregates query.
+const AGGREGATE_LIMIT: usize = 50000;
+
+impl From<&Aggregate> for Bar {
+    fn from(agg: &Aggregate) -> Self {
+        Self {
+            timestamp: agg.t,
+            open: agg.o,
+            high: agg.h,
+            low: agg.l,
+            close: agg.c,
+            volume: agg.v,
+            vwap: agg.vw,
+            transactions: agg.n,
+            session: Session::default(),
+        }
+    }
+}
+
+impl From<BarTimespan> for Timespan {
+    fn from(timespan: BarTimespan) -> Self {
+        match timespan {
+            BarTimespan::Minute => Timespan::Minute,
+            BarTimespan::Hour => Timespan::Hour,
+            BarTimespan::Day => Timespan::Day,
+        }
+    }
+}
+
+impl RequestError for Error {
+    fn is_transient(&self) -> bool {
+        is_transient_http(self)
+    }
+}
+
+/// Polygon bars and snapshots. Sources sharing a `requester` share its rate limit.
+pub struct PolygonSource {
+    client: Client<'static>,
+    requester: Arc<Requester>,
+}
+
+impl PolygonSource {
+    pub fn from_env(requester: Arc<Requester>) -> Result<Self> {
+        Ok(Self {
+            client: Client::from_env()?,
+            requester,
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn download_bars(
+        &self,
+        ticker: &str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        bar_size: BarSize,
+    ) -> Result<Vec<Bar>> {
+        debug!("Downloading price data");
+        let mut data: Vec<Bar> = Vec::new();
+        let mut from = start_date;
+        // Page through the window. A full page may have been cut off partway through its last
+        // day, so that day is dropped and requested again as the start of the next page.
+        while from <= end_date {
+            let description = format!("prices of {} from {}", ticker, from);
+            let response = self
+                .requester
+                .send(description, || {
+                    self.client.send(
+                        GetAggregate::new(ticker, from, end_date)
+                            .multiplier(bar_size.multiplier)
+                            .timespan(bar_size.timespan.into())
+                            .unadjusted(true)
+                            .limit(AGGREGATE_LIMIT as _),
+                    )
+                })
+                .await?;
+            let aggs = response.results.unwrap_or_default();
+            if aggs.len() < AGGREGATE_LIMIT {
+                data.extend(aggs.iter().map(Bar::from));
+                break;
+            }
+            let last_date = trading_date(&aggs[aggs.len() - 1].t);
+            if last_date <= from {
+                return Err(anyhow!(
+                    "Aggregates for {} on {} exceed the limit of {} bars",
+                    ticker,
+                    from,
+                    AGGREGATE_LIMIT
+                ));
+            }
+            debug!(%from, %last_date, "Response truncated, requesting next page");
+            data.extend(
+                aggs.iter()
+                    .filter(|agg| trading_date(&agg.t) < last_date)
+                    .map(Bar::from),
+            );
+            from = last_date;
+        }
+
+        Ok(data)
+    }
+}
+
+impl BarSource for PolygonSource {
+    fn bars<'a>(
+        &'a self,
+        ticker: &'a str,
+        start_date: NaiveDate,
+        end_date: NaiveDate,
+        bar_size: BarSize,
+    ) -> BoxFuture<'a, Result<Vec<Bar>>> {
+        self.download_bars(ticker, start_date, end_date, bar_size)
+            .boxed()
+    }
+}
+
+impl OpenCloseSource for PolygonSource {
+    fn open_close<'a>(
+        &'a self,
+        tickers: &'a [String],
+    ) -> BoxFuture<'a, Result<HashMap<String, (Decimal, Decimal)>>> {
+        async move {
+            debug!("Downloading overnight returns data");
+            let results = join_all(tickers.iter().map(|ticker| async move {
+                let snapshot = self
+                    .requester
+                    .send(format!("snapshot of {}", ticker), || {
+                        self.client.send(GetTickerSnapshot(ticker))
+                    })
+                    .await;
+                (ticker, snapshot)
+            }))
+            .await;
+            let mut open_close = HashMap::new();
+            let mut failures = Vec::new();
+            for (ticker, snapshot) in results {
+                match snapshot {
+                    Ok(snapshot) => {
+                        open_close.insert(
+                            snapshot.ticker.ticker,
+                            (snapshot.ticker.day.o, snapshot.ticker.previous_day.c),
+                        );
+                    }
+                    Err(e) => failures.push(FetchFailure {
+                        ticker: ticker.clone(),
+                        dataset: "open_close",
+                        error: e.to_string(),
+                    }),
+                }
+            }
+            check_failures(&failures, false)?;
+            Ok(open_close)
+        }
+        .boxed()
+    }
+}
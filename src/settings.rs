@@ -1,13 +1,401 @@
+use crate::data_download::BarSize;
+use chrono::NaiveDate;
 use config::{Config, ConfigError, Environment};
 use kafka_settings::KafkaSettings;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Deserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "run_mode", rename_all = "snake_case")]
 pub enum RunMode {
-    Download { out_file: String },
-    Run { data_file: String },
+    Download {
+        out_file: String,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+    InvalidateCache,
+    Calibrate {
+        data_file: String,
+        out_file: String,
+    },
+    Run {
+        data_file: String,
+    },
+    Backtest {
+        data_file: String,
+        pair_file: String,
+        out_file: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BarTimespan {
+    Minute,
+    Hour,
+    Day,
+}
+
+/// Lookback of the corporate-action endpoints, relative to today.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum CorporateActionRange {
+    OneMonth,
+    ThreeMonths,
+    SixMonths,
+    OneYear,
+    TwoYears,
+    FiveYears,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// One JSON object of columns per ticker
+    Json,
+    /// Long format, one row per ticker and bar
+    Csv,
+    /// Arrow IPC file in the same long format as `Csv`
+    Arrow,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Json
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionFilter {
+    /// Regular trading hours only, ending early on half-days
+    Regular,
+    /// Pre-market, regular and post-market trading from 4:00 to 20:00 Eastern
+    Extended,
+    /// Every bar returned by the source
+    All,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputLayout {
+    /// Timestamps and closes only
+    Close,
+    /// Every bar field
+    Ohlcv,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarketDataSource {
+    /// Polygon for bars and open/close, IEX for corporate actions
+    Api,
+    /// Files under `local_dir`, for tests and offline runs
+    Local,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct MarketDataSettings {
+    pub source: MarketDataSource,
+    /// Directory read by the local source.
+    pub local_dir: Option<String>,
+}
+
+impl Default for MarketDataSettings {
+    fn default() -> Self {
+        Self {
+            source: MarketDataSource::Api,
+            local_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct RequestSettings {
+    /// Retries after the first failed attempt of a request.
+    pub max_retries: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Maximum in-flight requests per API.
+    pub max_concurrent: usize,
+    /// Maximum new requests per second per API. Zero disables the limit.
+    pub requests_per_second: u32,
+}
+
+impl Default for RequestSettings {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            max_concurrent: 20,
+            requests_per_second: 50,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentMode {
+    /// History scaled to the latest basis
+    Backward,
+    /// Later prices scaled to the basis of the first bar
+    Forward,
+    /// Raw prices, as traded
+    Unadjusted,
+}
+
+/// Treatment of tickers whose dividends or splits couldn't be fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingActionPolicy {
+    /// Adjust for whatever corporate actions are available
+    AssumeNone,
+    /// Leave the ticker out of the output
+    Skip,
+    /// Fail the run, listing the affected tickers
+    Fail,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct QualitySettings {
+    /// JSON file the quality report is written to. The report is only logged when unset.
+    pub report_file: Option<String>,
+    /// Fail the run before writing any data if a threshold below is breached.
+    pub fail_on_breach: bool,
+    /// Largest acceptable share of expected regular-session bars that are missing.
+    pub max_missing_ratio: Decimal,
+    /// Longest acceptable stretch of a regular session without bars.
+    pub max_gap_minutes: i64,
+    /// Longest acceptable run of consecutive zero-volume bars.
+    pub max_zero_volume_bars: usize,
+    /// Relative close-to-close move between consecutive bars of a session counted as an outlier.
+    pub jump_threshold: Decimal,
+    /// Number of outlier jumps tolerated per ticker.
+    pub max_jumps: usize,
+}
+
+impl Default for QualitySettings {
+    fn default() -> Self {
+        Self {
+            report_file: None,
+            fail_on_breach: false,
+            max_missing_ratio: Decimal::new(5, 2),
+            max_gap_minutes: 30,
+            max_zero_volume_bars: 12,
+            jump_threshold: Decimal::new(1, 1),
+            max_jumps: 0,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    /// First day of the price window. Defaults to `lookback` business days before `end_date`.
+    pub start_date: Option<NaiveDate>,
+    /// Last day of the price window. Defaults to the previous business day.
+    pub end_date: Option<NaiveDate>,
+    /// Length of the price window in business days, used when `start_date` is unset.
+    pub lookback: i32,
+    pub bar_multiplier: u32,
+    pub bar_timespan: BarTimespan,
+    /// Bar size written out, aggregated from the downloaded bars. Defaults to the downloaded size.
+    pub resample: Option<BarSize>,
+    pub sessions: SessionFilter,
+    /// Corporate-action range. It is widened if it doesn't reach back to `start_date`.
+    pub corporate_action_range: Option<CorporateActionRange>,
+    pub layout: OutputLayout,
+    /// Directory of raw bars kept between runs. Only missing days are downloaded when set.
+    pub cache_dir: Option<String>,
+    pub requests: RequestSettings,
+    /// Fail the run if any ticker still can't be fetched after retrying. Otherwise continue
+    /// without it.
+    pub fail_on_error: bool,
+    /// Treatment of tickers without dividend or split data when adjusting prices.
+    pub missing_actions: MissingActionPolicy,
+    pub adjustment: AdjustmentMode,
+    /// JSON file listing the corporate actions and adjustment factors applied to each ticker.
+    pub audit_file: Option<String>,
+    /// CSV of renames, delistings and suspensions (`ticker,date,event,new_ticker`). Renamed
+    /// tickers are stitched together from their earlier symbols.
+    pub symbol_changes: Option<String>,
+    pub quality: QualitySettings,
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        Self {
+            start_date: None,
+            end_date: None,
+            lookback: 100,
+            bar_multiplier: 5,
+            bar_timespan: BarTimespan::Minute,
+            resample: None,
+            sessions: SessionFilter::Regular,
+            corporate_action_range: None,
+            layout: OutputLayout::Ohlcv,
+            cache_dir: None,
+            requests: RequestSettings::default(),
+            fail_on_error: true,
+            missing_actions: MissingActionPolicy::AssumeNone,
+            adjustment: AdjustmentMode::Backward,
+            audit_file: None,
+            symbol_changes: None,
+            quality: QualitySettings::default(),
+        }
+    }
+}
+
+impl DownloadSettings {
+    /// Size of the downloaded bars.
+    pub fn bar_size(&self) -> BarSize {
+        BarSize {
+            multiplier: self.bar_multiplier,
+            timespan: self.bar_timespan,
+        }
+    }
+
+    /// Size of the bars written out, which calibration and backtests read back.
+    pub fn output_bar_size(&self) -> BarSize {
+        self.resample.unwrap_or_else(|| self.bar_size())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct CalibrationSettings {
+    /// Optional CSV of `asset_1,asset_2` candidates. All combinations of downloaded tickers are
+    /// used when unset.
+    pub candidates_file: Option<String>,
+    /// Number of most recent bars used for the short-term spread and its volatility.
+    pub short_term_bars: usize,
+    /// Multiple of the short-term spread standard deviation used as the band width.
+    pub epsilon_multiplier: Decimal,
+    /// Pairs with fewer overlapping bars than this are skipped.
+    pub min_observations: usize,
+    /// Only pair tickers from the same universe sector when candidates aren't given.
+    pub same_sector: bool,
+    /// Bar size the data file is aggregated to before calibrating, so that a coarser frequency
+    /// can be tried without re-downloading. `short_term_bars` counts bars of this size.
+    pub resample: Option<BarSize>,
+}
+
+impl Default for CalibrationSettings {
+    fn default() -> Self {
+        Self {
+            candidates_file: None,
+            // Five trading days of regular-session 5-minute bars
+            short_term_bars: 390,
+            epsilon_multiplier: Decimal::new(2, 0),
+            min_observations: 1000,
+            same_sector: false,
+            resample: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BacktestSettings {
+    /// Bar size the data file is aggregated to before replaying it.
+    pub resample: Option<BarSize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct UniverseSettings {
+    /// CSV (`ticker,sector,exchange,tags`) or `.json` universe used instead of `tickers`.
+    pub file: Option<String>,
+    /// Tickers whose last downloaded close is below this are dropped.
+    pub min_price: Option<Decimal>,
+    /// Tickers whose average daily dollar volume over the download window is below this are
+    /// dropped.
+    pub min_dollar_volume: Option<Decimal>,
+    /// Where the download mode writes the universe left after filtering, for calibration and
+    /// trading.
+    pub filtered_file: Option<String>,
+}
+
+/// How the dollars committed to a pair are split between its legs.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LegSizing {
+    /// Equal dollars in both legs
+    DollarNeutral,
+    /// Dollars in `asset_2` are the hedge ratio times those in `asset_1`
+    BetaNeutral,
+}
+
+/// When a held pair is closed out.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitRule {
+    /// Hold until the opposite band is breached, then flip
+    Flip,
+    /// Go flat once the spread crosses equilibrium
+    Equilibrium,
+    /// Go flat once the spread has moved `exit_band_fraction` of the way from the entry band
+    /// to equilibrium
+    BandFraction,
+}
+
+/// How many dollars are committed to each pair. The `dollars` column of the pair file overrides
+/// any of these.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SizingMode {
+    /// `pair_dollars` for every pair
+    Fixed,
+    /// `cash` split equally across the day's pairs
+    EqualSplit,
+    /// `cash` split across the day's pairs in inverse proportion to their calibrated epsilon
+    InverseVolatility,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct TradingSettings {
+    pub leg_sizing: LegSizing,
+    pub sizing: SizingMode,
+    /// Dollars per pair for fixed sizing. Defaults to half of `cash`.
+    pub pair_dollars: Option<Decimal>,
+    /// Send one intent per ticker for the exposure summed across pairs, instead of one per pair
+    /// and leg.
+    pub net_intents: bool,
+    pub exit_rule: ExitRule,
+    pub exit_band_fraction: Decimal,
+    /// Topic fills are published on, read both live and when seeding positions on startup.
+    pub lots_topic: String,
+}
+
+impl TradingSettings {
+    /// Fraction of the way from the entry band to equilibrium at which held pairs go flat, if
+    /// they do at all.
+    pub fn exit_fraction(&self) -> Option<Decimal> {
+        match self.exit_rule {
+            ExitRule::Flip => None,
+            ExitRule::Equilibrium => Some(Decimal::ONE),
+            ExitRule::BandFraction => Some(self.exit_band_fraction),
+        }
+    }
+}
+
+impl Default for TradingSettings {
+    fn default() -> Self {
+        Self {
+            leg_sizing: LegSizing::DollarNeutral,
+            sizing: SizingMode::Fixed,
+            pair_dollars: None,
+            net_intents: false,
+            exit_rule: ExitRule::Flip,
+            exit_band_fraction: Decimal::new(5, 1),
+            lots_topic: "lots".to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -17,6 +405,18 @@ pub struct AppSettings {
     pub run_mode: RunMode,
     #[serde(deserialize_with = "vec_from_str", default)]
     pub tickers: Vec<String>,
+    #[serde(default)]
+    pub market_data: MarketDataSettings,
+    #[serde(default)]
+    pub download: DownloadSettings,
+    #[serde(default)]
+    pub calibration: CalibrationSettings,
+    #[serde(default)]
+    pub backtest: BacktestSettings,
+    #[serde(default)]
+    pub universe: UniverseSettings,
+    #[serde(default)]
+    pub trading: TradingSettings,
 }
 
 pub fn vec_from_str<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
@@ -1,42 +1,79 @@
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
 use csv::Reader;
-use polygon::rest::{Client, GetTickerSnapshot};
 use rust_decimal::prelude::*;
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
-use tracing::debug;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TradePair {
     pub asset_1: String,
     pub asset_2: String,
     pub original_lt_spread: Decimal,
     pub original_st_spread: Decimal,
     pub epsilon: Decimal,
+    /// Beta of `asset_1` on `asset_2`, so that the spread is `ln p1 - beta * ln p2`. One when
+    /// the column is missing or empty, and must be positive so that the legs trade in opposite
+    /// directions.
+    #[serde(default)]
+    pub hedge_ratio: Option<Decimal>,
+    /// Dollars committed to the pair, overriding the sizing policy.
+    #[serde(default)]
+    pub dollars: Option<Decimal>,
 }
 
 pub fn read_data<T: AsRef<Path>>(file: T) -> Result<Vec<TradePair>> {
     let mut reader = Reader::from_path(file)?;
-    Ok(reader.deserialize().filter_map(|x| x.ok()).collect())
-}
-
-#[tracing::instrument(skip(client, tickers))]
-pub async fn open_close<'a, T: Iterator<Item = &'a str> + 'a>(
-    client: &Client<'_>,
-    tickers: T,
-) -> HashMap<String, (Decimal, Decimal)> {
-    debug!("Downloading overnight returns data");
-    let reqs = tickers.map(|ticker| GetTickerSnapshot(ticker));
-    let results = client.send_all(reqs).await;
-    results
-        .into_iter()
-        .flat_map(|res| res.ok())
-        .map(|snapshot| {
-            (
-                snapshot.ticker.ticker,
-                (snapshot.ticker.day.o, snapshot.ticker.previous_day.c),
-            )
+    reader
+        .deserialize()
+        .enumerate()
+        .map(|(i, row)| {
+            // One-based and after the header
+            let line = i + 2;
+            let pair: TradePair = row.with_context(|| format!("Invalid pair on line {}", line))?;
+            if let Some(hedge_ratio) = pair.hedge_ratio {
+                ensure!(
+                    hedge_ratio > Decimal::ZERO,
+                    "Hedge ratio of {}-{} on line {} must be positive, got {}",
+                    pair.asset_1,
+                    pair.asset_2,
+                    line,
+                    hedge_ratio
+                );
+            }
+            Ok(pair)
         })
         .collect()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_read_data() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let file = temp_dir.path().join("pairs.csv");
+        let header = "asset_1,asset_2,original_lt_spread,original_st_spread,epsilon,hedge_ratio\n";
+        fs::write(
+            &file,
+            format!("{}AAPL,MSFT,0,0,0.1,1.5\nAAPL,GOOGL,0,0,0.1,\n", header),
+        )
+        .unwrap();
+        let pairs = read_data(&file).unwrap();
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].hedge_ratio, Some(Decimal::new(15, 1)));
+        assert_eq!(pairs[1].hedge_ratio, None);
+
+        fs::write(&file, format!("{}AAPL,MSFT,0,0,0.1,-0.5\n", header)).unwrap();
+        assert!(read_data(&file).is_err());
+        fs::write(
+            &file,
+            format!("{}AAPL,MSFT,0,0,0.1,1\nAAPL,GOOGL,0,0,0.1,x\n", header),
+        )
+        .unwrap();
+        let error = read_data(&file).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid pair on line 3");
+        temp_dir.close().unwrap();
+    }
+}
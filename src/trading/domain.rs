@@ -1,4 +1,6 @@
+use crate::settings::LegSizing;
 use crate::trading::data::TradePair;
+use crate::trading::positions::PairHolding;
 use rust_decimal::prelude::*;
 use tracing::{debug, info};
 
@@ -8,6 +10,12 @@ pub enum Position {
     RetainLong,
     RetainShort,
     Short,
+    Flat,
+}
+
+/// Log-price spread `ln p1 - hedge_ratio * ln p2`.
+pub fn log_spread(price_1: &Decimal, price_2: &Decimal, hedge_ratio: Decimal) -> Decimal {
+    price_1.ln() - hedge_ratio * price_2.ln()
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -18,6 +26,9 @@ pub struct TradeBands {
     pub equilibrium: Decimal,
     pub lower_band: Decimal,
     pub original_st_spread: Decimal,
+    pub hedge_ratio: Decimal,
+    /// Dollars committed to the pair when set in the pair file.
+    pub dollars: Option<Decimal>,
 }
 
 impl TradeBands {
@@ -31,12 +42,31 @@ impl TradeBands {
             equilibrium,
             lower_band,
             original_st_spread: trade_pair.original_st_spread,
+            hedge_ratio: trade_pair.hedge_ratio.unwrap_or(Decimal::ONE),
+            dollars: trade_pair.dollars,
+        }
+    }
+
+    /// Log-price spread `ln p1 - beta * ln p2`.
+    pub fn spread(&self, price_1: &Decimal, price_2: &Decimal) -> Decimal {
+        log_spread(price_1, price_2, self.hedge_ratio)
+    }
+
+    /// Dollars in `asset_1` and `asset_2` when `dollars` are committed to the pair in total.
+    pub fn leg_dollars(&self, dollars: Decimal, sizing: LegSizing) -> (Decimal, Decimal) {
+        let half = dollars / Decimal::new(2, 0);
+        match sizing {
+            LegSizing::DollarNeutral => (half, half),
+            LegSizing::BetaNeutral => {
+                let leg_1 = dollars / (Decimal::ONE + self.hedge_ratio);
+                (leg_1, dollars - leg_1)
+            }
         }
     }
 
     #[tracing::instrument]
     pub fn trade_signal(&self, price_1: &Decimal, price_2: &Decimal) -> Position {
-        let spread = (price_1.ln() - price_2.ln()) - self.original_st_spread;
+        let spread = self.spread(price_1, price_2) - self.original_st_spread;
         debug!(asset_1 = %self.asset_1, asset_2 = %self.asset_2, band_ratio = %((spread - self.lower_band) / (self.upper_band - self.lower_band)));
         if spread > self.upper_band {
             info!("Upper band breached, going short");
@@ -50,4 +80,97 @@ impl TradeBands {
             Position::RetainLong
         }
     }
+
+    /// `trade_signal`, except that a held pair goes flat once its spread has moved
+    /// `exit_fraction` of the way from the entry band to equilibrium. Without an exit fraction,
+    /// pairs are held until the opposite band is breached.
+    pub fn signal(
+        &self,
+        price_1: &Decimal,
+        price_2: &Decimal,
+        holding: PairHolding,
+        exit_fraction: Option<Decimal>,
+    ) -> Position {
+        let position = self.trade_signal(price_1, price_2);
+        let fraction = match exit_fraction {
+            Some(fraction) => fraction,
+            None => return position,
+        };
+        let spread = self.spread(price_1, price_2) - self.original_st_spread;
+        let long_exit = self.lower_band + fraction * (self.equilibrium - self.lower_band);
+        let short_exit = self.upper_band - fraction * (self.upper_band - self.equilibrium);
+        match (&position, holding) {
+            (Position::RetainLong | Position::RetainShort, PairHolding::Long)
+                if spread >= long_exit =>
+            {
+                info!("Long spread reverted, going flat");
+                Position::Flat
+            }
+            (Position::RetainLong | Position::RetainShort, PairHolding::Short)
+                if spread <= short_exit =>
+            {
+                info!("Short spread reverted, going flat");
+                Position::Flat
+            }
+            _ => position,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_hedge_ratio() {
+        let pair = TradeBands::new(
+            TradePair {
+                asset_1: "AAPL".to_string(),
+                asset_2: "MSFT".to_string(),
+                original_lt_spread: Decimal::ZERO,
+                original_st_spread: Decimal::ZERO,
+                epsilon: Decimal::new(1, 1),
+                hedge_ratio: Some(Decimal::new(3, 0)),
+                dollars: None,
+            },
+            Decimal::ZERO,
+        );
+        let (p1, p2) = (Decimal::new(8, 0), Decimal::new(2, 0));
+        // `Decimal::ln` is only accurate to a few decimal places
+        assert!(pair.spread(&p1, &p2).abs() < Decimal::new(1, 3));
+        // Over the upper band with a 1:1 spread, but not once weighted by the hedge ratio
+        let p2 = Decimal::new(195, 2);
+        assert_eq!(pair.trade_signal(&p1, &p2), Position::RetainShort);
+
+        // A long pair exits halfway back to equilibrium, at a spread of -0.05
+        let p2 = Decimal::new(203, 2);
+        assert_eq!(pair.trade_signal(&p1, &p2), Position::RetainLong);
+        let half = Some(Decimal::new(5, 1));
+        assert_eq!(
+            pair.signal(&p1, &p2, PairHolding::Long, half),
+            Position::Flat
+        );
+        assert_eq!(
+            pair.signal(&p1, &p2, PairHolding::Long, Some(Decimal::ONE)),
+            Position::RetainLong
+        );
+        assert_eq!(
+            pair.signal(&p1, &p2, PairHolding::Long, None),
+            Position::RetainLong
+        );
+        assert_eq!(
+            pair.signal(&p1, &p2, PairHolding::Short, half),
+            Position::Flat
+        );
+
+        let dollars = Decimal::new(1000, 0);
+        assert_eq!(
+            pair.leg_dollars(dollars, LegSizing::DollarNeutral),
+            (Decimal::new(500, 0), Decimal::new(500, 0))
+        );
+        assert_eq!(
+            pair.leg_dollars(dollars, LegSizing::BetaNeutral),
+            (Decimal::new(250, 0), Decimal::new(750, 0))
+        );
+    }
 }
@@ -1,50 +1,115 @@
+use crate::calendar::{extended_hours, regular_session, trading_date};
+use crate::market_data::OpenCloseSource;
+use crate::settings::TradingSettings;
+use crate::universe::Universe;
 use anyhow::Result;
-use domain::TradeBands;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use data::TradePair;
+use domain::{log_spread, TradeBands};
 use kafka_settings::{consumer, producer, KafkaSettings};
-use polygon::rest::Client;
+use rdkafka::consumer::Consumer;
 use rust_decimal::prelude::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::iter::once;
 use std::path::Path;
 use tokio::sync::mpsc::unbounded_channel;
 use tracing::{debug, info};
 
-mod data;
-mod domain;
+pub mod data;
+pub mod domain;
+mod netting;
+pub mod positions;
 mod relay;
+pub mod sizing;
 mod trade_generator;
-use relay::Relay;
+use positions::PositionTracker;
+use relay::{replay_lots, Relay};
+use sizing::sizing_policy;
 use trade_generator::TradeGenerator;
 
-pub async fn run<T: AsRef<Path>>(cash: Decimal, data_file: T, kafka: KafkaSettings) -> Result<()> {
-    info!("Starting double-trouble");
-    let client = Client::from_env()?;
-    let producer = producer(&kafka)?;
-    let consumer = consumer(&kafka)?;
-    let trade_pairs = data::read_data(data_file)?;
-    let tickers: HashSet<String> = trade_pairs
-        .iter()
-        .flat_map(|pair| once(pair.asset_1.clone()).chain(once(pair.asset_2.clone())))
-        .collect();
-    let open_close = data::open_close(&client, tickers.iter().map(|s| s.as_ref())).await;
-    let pairs: Vec<TradeBands> = trade_pairs
+/// How long before the regular close positions are wound down and outstanding intents expire.
+pub const WIND_DOWN_MINUTES: i64 = 10;
+
+/// Time on `date` at which positions are wound down, accounting for early closes.
+pub fn wind_down_time(date: NaiveDate) -> DateTime<Utc> {
+    regular_session(date).1 - Duration::minutes(WIND_DOWN_MINUTES)
+}
+
+/// Build the day's `TradeBands`, centering each pair's bands on the average of its opening and
+/// previous closing spreads. Pairs missing either ticker in `open_close` are dropped.
+pub fn trade_bands(
+    trade_pairs: Vec<TradePair>,
+    open_close: &HashMap<String, (Decimal, Decimal)>,
+) -> Vec<TradeBands> {
+    trade_pairs
         .into_iter()
         .filter_map(|pair| {
             let opt1 = open_close.get(&pair.asset_1);
             let opt2 = open_close.get(&pair.asset_2);
+            let beta = pair.hedge_ratio.unwrap_or(Decimal::ONE);
             opt1.zip(opt2).map(|((op1, cl1), (op2, cl2))| {
-                let equilibrium = (((op1.ln() - op2.ln()) - pair.original_st_spread)
-                    + ((cl1.ln() - cl2.ln()) - pair.original_st_spread))
+                let equilibrium = ((log_spread(op1, op2, beta) - pair.original_st_spread)
+                    + (log_spread(cl1, cl2, beta) - pair.original_st_spread))
                     / Decimal::new(2, 0);
                 TradeBands::new(pair, equilibrium)
             })
         })
         .inspect(|pair| debug!("Pair: {:?}", pair))
+        .collect()
+}
+
+pub async fn run<T: AsRef<Path>>(
+    cash: Decimal,
+    data_file: T,
+    universe: Option<&Universe>,
+    open_close_source: &dyn OpenCloseSource,
+    settings: &TradingSettings,
+    mut kafka: KafkaSettings,
+) -> Result<()> {
+    info!("Starting double-trouble");
+    if !kafka.input_topics.contains(&settings.lots_topic) {
+        kafka.input_topics.push(settings.lots_topic.clone());
+    }
+    let producer = producer(&kafka)?;
+    let consumer = consumer(&kafka)?;
+    let mut trade_pairs = data::read_data(data_file)?;
+    if let Some(universe) = universe {
+        trade_pairs = universe.retain_pairs(trade_pairs);
+    }
+    let tickers: HashSet<String> = trade_pairs
+        .iter()
+        .flat_map(|pair| once(pair.asset_1.clone()).chain(once(pair.asset_2.clone())))
         .collect();
+    let open_close = open_close_source
+        .open_close(&tickers.iter().cloned().collect::<Vec<_>>())
+        .await?;
+    let pairs = trade_bands(trade_pairs, &open_close);
+
+    // Seed positions with today's lots so that pairs held before a restart aren't seen as flat
+    let mut positions = PositionTracker::new(&pairs);
+    // A group of its own, so that replaying neither joins the live group nor commits its offsets
+    let replay_settings = KafkaSettings {
+        group_id: format!("{}-replay", kafka.group_id),
+        input_topics: vec![settings.lots_topic.clone()],
+        ..kafka.clone()
+    };
+    let replay_consumer = kafka_settings::consumer(&replay_settings)?;
+    replay_consumer.unsubscribe();
+    let (start_of_day, _) = extended_hours(trading_date(&Utc::now()));
+    for (lot, offset) in replay_lots(&replay_consumer, &settings.lots_topic, start_of_day).await? {
+        positions.record(&lot, offset);
+    }
 
     let (tx, rx) = unbounded_channel();
-    let relay = Relay::new(tickers, consumer, tx);
-    let mut trade_generator = TradeGenerator::new(cash, pairs, rx, producer);
+    let relay = Relay::new(tickers, settings.lots_topic.clone(), consumer, tx);
+    let mut trade_generator = TradeGenerator::new(
+        sizing_policy(settings, cash, &pairs),
+        pairs,
+        settings,
+        positions,
+        rx,
+        producer,
+    );
 
     tokio::select! {
         _ = trade_generator.run() => Ok(()),
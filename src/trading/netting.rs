@@ -0,0 +1,216 @@
+use crate::trading::domain::Position;
+use crate::trading::positions::PairHolding;
+use rust_decimal::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// Dollars a pair wants held in one of its legs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LegTarget {
+    pub pair: String,
+    pub ticker: String,
+    pub dollars: Decimal,
+    /// Whether the pair's signal would change what it holds.
+    pub changed: bool,
+}
+
+/// Exposure to a ticker summed across every pair trading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NettedTarget {
+    pub ticker: String,
+    pub dollars: Decimal,
+    /// Dollars contributed by each pair.
+    pub attribution: Vec<(String, Decimal)>,
+    pub changed: bool,
+}
+
+/// Target dollars in each leg of a pair given its signal, what it holds, and its entry sizes.
+/// Retain signals keep an entry in the retained direction and otherwise target flat.
+pub fn leg_targets(
+    signal: &Position,
+    holding: PairHolding,
+    (dollars_1, dollars_2): (Decimal, Decimal),
+) -> (Decimal, Decimal) {
+    let long = (dollars_1, -dollars_2);
+    let short = (-dollars_1, dollars_2);
+    let flat = (Decimal::ZERO, Decimal::ZERO);
+    match (signal, holding) {
+        (Position::Long, _) | (Position::RetainLong, PairHolding::Long) => long,
+        (Position::Short, _) | (Position::RetainShort, PairHolding::Short) => short,
+        _ => flat,
+    }
+}
+
+/// Whether `shares` held of a ticker fill a netted target of `dollars`, i.e. are flat for a flat
+/// target and otherwise on the target's side. Partial fills count, so price moves don't cause
+/// the target to be resent.
+pub fn is_filled(shares: Decimal, dollars: Decimal) -> bool {
+    if dollars.is_zero() {
+        shares.is_zero()
+    } else {
+        shares * dollars > Decimal::ZERO
+    }
+}
+
+/// Holding of each pair implied by the targets last netted for it. Netted intents carry no
+/// sub-strategy, so their fills can't be attributed to the pairs sharing a ticker. Targets that
+/// never fill are resent while the ticker's tracked position doesn't fill them, see `is_filled`.
+#[derive(Debug, Default)]
+pub struct NettedHoldings(HashMap<String, PairHolding>);
+
+impl NettedHoldings {
+    /// What `pair` holds once its last targets are filled, or `tracked` before it has any.
+    pub fn holding<F: FnOnce() -> PairHolding>(&self, pair: &str, tracked: F) -> PairHolding {
+        self.0.get(pair).copied().unwrap_or_else(tracked)
+    }
+
+    /// Leg targets of `pair` for `signal`, remembered as what it holds on the next tick.
+    pub fn targets(
+        &mut self,
+        pair: &str,
+        signal: &Position,
+        holding: PairHolding,
+        dollars: (Decimal, Decimal),
+    ) -> (Decimal, Decimal) {
+        let (target_1, target_2) = leg_targets(signal, holding, dollars);
+        self.0
+            .insert(pair.to_string(), PairHolding::new(target_1, target_2));
+        (target_1, target_2)
+    }
+}
+
+/// Sum the leg targets of every pair per ticker, in ticker order.
+pub fn net_targets(targets: Vec<LegTarget>) -> Vec<NettedTarget> {
+    let mut netted: BTreeMap<String, NettedTarget> = BTreeMap::new();
+    for target in targets {
+        let entry = netted
+            .entry(target.ticker.clone())
+            .or_insert_with(|| NettedTarget {
+                ticker: target.ticker.clone(),
+                dollars: Decimal::ZERO,
+                attribution: Vec::new(),
+                changed: false,
+            });
+        entry.dollars += target.dollars;
+        entry.attribution.push((target.pair, target.dollars));
+        entry.changed |= target.changed;
+    }
+    netted.into_values().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trading::positions::pair_key;
+
+    #[test]
+    fn test_net_targets() {
+        let legs = (Decimal::new(100, 0), Decimal::new(100, 0));
+        let (aapl, msft_1) = leg_targets(&Position::Long, PairHolding::Flat, legs);
+        let (googl, msft_2) = leg_targets(&Position::RetainShort, PairHolding::Short, legs);
+        let (xom, cvx) = leg_targets(&Position::RetainLong, PairHolding::Short, legs);
+        assert_eq!((xom, cvx), (Decimal::ZERO, Decimal::ZERO));
+
+        let target = |pair: &str, ticker: &str, dollars, changed| LegTarget {
+            pair: pair.to_string(),
+            ticker: ticker.to_string(),
+            dollars,
+            changed,
+        };
+        let netted = net_targets(vec![
+            target("AAPL-MSFT", "AAPL", aapl, true),
+            target("AAPL-MSFT", "MSFT", msft_1, true),
+            target("GOOGL-MSFT", "GOOGL", googl, false),
+            target("GOOGL-MSFT", "MSFT", msft_2, false),
+        ]);
+        let tickers: Vec<&str> = netted.iter().map(|t| t.ticker.as_str()).collect();
+        assert_eq!(tickers, vec!["AAPL", "GOOGL", "MSFT"]);
+        let msft = &netted[2];
+        // Short one pair's leg and long the other's, so nothing is traded
+        assert_eq!(msft.dollars, Decimal::ZERO);
+        assert_eq!(
+            msft.attribution,
+            vec![
+                ("AAPL-MSFT".to_string(), Decimal::new(-100, 0)),
+                ("GOOGL-MSFT".to_string(), Decimal::new(100, 0)),
+            ]
+        );
+        assert!(msft.changed);
+        assert!(!netted[1].changed);
+    }
+
+    #[test]
+    fn test_netted_holdings() {
+        let legs = (Decimal::new(100, 0), Decimal::new(100, 0));
+        let mut holdings = NettedHoldings::default();
+        // Fills of netted intents leave MSFT flat, so it reads as half of each pair
+        let tracked = || PairHolding::Partial;
+        let tick = |holdings: &mut NettedHoldings, signals: [(&str, &str, Position); 2]| {
+            let mut targets = Vec::new();
+            for (asset_1, asset_2, signal) in signals.iter() {
+                let pair = pair_key(asset_1, asset_2);
+                let holding = holdings.holding(&pair, tracked);
+                let changed = holding.requires_intents(signal);
+                let (target_1, target_2) = holdings.targets(&pair, signal, holding, legs);
+                for (ticker, dollars) in [(asset_1, target_1), (asset_2, target_2)] {
+                    targets.push(LegTarget {
+                        pair: pair.clone(),
+                        ticker: ticker.to_string(),
+                        dollars,
+                        changed,
+                    });
+                }
+            }
+            net_targets(targets)
+        };
+
+        let entered = tick(
+            &mut holdings,
+            [
+                ("AAPL", "MSFT", Position::Long),
+                ("GOOGL", "MSFT", Position::Short),
+            ],
+        );
+        assert!(entered.iter().all(|target| target.changed));
+        // Both pairs hold on to their entries rather than closing them
+        for _ in 0..2 {
+            let held = tick(
+                &mut holdings,
+                [
+                    ("AAPL", "MSFT", Position::RetainLong),
+                    ("GOOGL", "MSFT", Position::RetainShort),
+                ],
+            );
+            let dollars: Vec<Decimal> = held.iter().map(|target| target.dollars).collect();
+            assert_eq!(
+                dollars,
+                vec![Decimal::new(100, 0), Decimal::new(-100, 0), Decimal::ZERO]
+            );
+            assert!(held.iter().all(|target| !target.changed));
+        }
+        assert_eq!(holdings.holding("AAPL-MSFT", tracked), PairHolding::Long);
+        assert_eq!(holdings.holding("GOOGL-MSFT", tracked), PairHolding::Short);
+
+        let exited = tick(
+            &mut holdings,
+            [
+                ("AAPL", "MSFT", Position::Flat),
+                ("GOOGL", "MSFT", Position::RetainShort),
+            ],
+        );
+        assert_eq!(exited[0].dollars, Decimal::ZERO);
+        assert!(exited[0].changed);
+        assert_eq!(exited[2].dollars, Decimal::new(100, 0));
+        assert_eq!(holdings.holding("AAPL-MSFT", tracked), PairHolding::Flat);
+    }
+
+    #[test]
+    fn test_is_filled() {
+        let (shares, dollars) = (Decimal::new(10, 0), Decimal::new(100, 0));
+        assert!(is_filled(shares, dollars));
+        assert!(is_filled(-shares, -dollars));
+        assert!(!is_filled(Decimal::ZERO, dollars));
+        assert!(!is_filled(-shares, dollars));
+        assert!(is_filled(Decimal::ZERO, Decimal::ZERO));
+        assert!(!is_filled(shares, Decimal::ZERO));
+    }
+}
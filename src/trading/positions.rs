@@ -0,0 +1,259 @@
+use crate::trading::domain::{Position, TradeBands};
+use chrono::{DateTime, Utc};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, info};
+
+/// A fill published on the lots topic. Negative `shares` are sells.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Lot {
+    pub ticker: String,
+    pub fill_time: DateTime<Utc>,
+    pub price: Decimal,
+    pub shares: Decimal,
+    #[serde(default)]
+    pub strategy: Option<String>,
+    #[serde(default)]
+    pub sub_strategy: Option<String>,
+}
+
+/// Where a lot was read from on Kafka, so that a lot both replayed on startup and received by the
+/// relay is only recorded once.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LotOffset {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Holding {
+    pub shares: Decimal,
+    pub average_price: Decimal,
+}
+
+impl Holding {
+    fn fill(&mut self, shares: Decimal, price: Decimal) {
+        let total = self.shares + shares;
+        if total.is_zero() {
+            self.average_price = Decimal::ZERO;
+        } else if self.shares.is_zero() || (self.shares > Decimal::ZERO) != (total > Decimal::ZERO)
+        {
+            // Opened or flipped, so the remaining shares were all bought at `price`
+            self.average_price = price;
+        } else if (shares > Decimal::ZERO) == (self.shares > Decimal::ZERO) {
+            self.average_price = (self.average_price * self.shares + price * shares) / total;
+        }
+        self.shares = total;
+    }
+}
+
+/// What is held of a pair, long meaning long `asset_1` and short `asset_2`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PairHolding {
+    Flat,
+    Long,
+    Short,
+    /// Only one leg, or both legs in the same direction, e.g. while an entry is being filled
+    Partial,
+}
+
+impl PairHolding {
+    pub fn new(leg_1: Decimal, leg_2: Decimal) -> Self {
+        let zero = Decimal::ZERO;
+        if leg_1.is_zero() && leg_2.is_zero() {
+            PairHolding::Flat
+        } else if leg_1 > zero && leg_2 < zero {
+            PairHolding::Long
+        } else if leg_1 < zero && leg_2 > zero {
+            PairHolding::Short
+        } else {
+            PairHolding::Partial
+        }
+    }
+
+    /// Whether intents for `signal` would change what is held. Entries already filled and
+    /// retain signals that keep the current holding are skipped.
+    pub fn requires_intents(self, signal: &Position) -> bool {
+        match signal {
+            Position::Long => self != PairHolding::Long,
+            Position::Short => self != PairHolding::Short,
+            Position::RetainLong => matches!(self, PairHolding::Short | PairHolding::Partial),
+            Position::RetainShort => matches!(self, PairHolding::Long | PairHolding::Partial),
+            Position::Flat => self != PairHolding::Flat,
+        }
+    }
+}
+
+/// Sub-strategy under which a pair's intents are sent and its lots are attributed.
+pub fn pair_key(asset_1: &str, asset_2: &str) -> String {
+    format!("{}-{}", asset_1, asset_2)
+}
+
+/// Shares and average prices held per ticker and per pair, built from the lots topic.
+#[derive(Debug, Default)]
+pub struct PositionTracker {
+    tickers: HashMap<String, Holding>,
+    pairs: HashMap<String, HashMap<String, Holding>>,
+    /// Number of traded pairs each ticker is a leg of.
+    pair_counts: HashMap<String, usize>,
+    recorded: HashSet<LotOffset>,
+}
+
+impl PositionTracker {
+    pub fn new(pairs: &[TradeBands]) -> Self {
+        let mut pair_counts: HashMap<String, usize> = HashMap::new();
+        for pair in pairs {
+            *pair_counts.entry(pair.asset_1.clone()).or_default() += 1;
+            *pair_counts.entry(pair.asset_2.clone()).or_default() += 1;
+        }
+        Self {
+            pair_counts,
+            ..Self::default()
+        }
+    }
+
+    /// Record a fill, unless the lot at `offset` was already recorded.
+    pub fn record(&mut self, lot: &Lot, offset: LotOffset) {
+        if !self.recorded.insert(offset) {
+            debug!(ticker = %lot.ticker, "Lot already recorded");
+            return;
+        }
+        let ticker = self.tickers.entry(lot.ticker.clone()).or_default();
+        ticker.fill(lot.shares, lot.price);
+        info!(
+            ticker = %lot.ticker,
+            shares = %ticker.shares,
+            average_price = %ticker.average_price,
+            "Position updated"
+        );
+        if let Some(sub_strategy) = lot.sub_strategy.as_ref() {
+            self.pairs
+                .entry(sub_strategy.clone())
+                .or_default()
+                .entry(lot.ticker.clone())
+                .or_default()
+                .fill(lot.shares, lot.price);
+        }
+    }
+
+    pub fn ticker(&self, ticker: &str) -> Holding {
+        self.tickers.get(ticker).copied().unwrap_or_default()
+    }
+
+    /// Holding of a pair, from the lots attributed to it or, if there are none, from the overall
+    /// position of legs that no other pair trades. Legs shared with other pairs can't be told
+    /// apart without attribution and count as flat.
+    pub fn pair(&self, asset_1: &str, asset_2: &str) -> PairHolding {
+        let legs = self.pairs.get(&pair_key(asset_1, asset_2));
+        let leg = |ticker: &str| match legs {
+            Some(legs) => legs.get(ticker).map_or(Decimal::ZERO, |h| h.shares),
+            None if self.pair_counts.get(ticker) == Some(&1) => self.ticker(ticker).shares,
+            None => Decimal::ZERO,
+        };
+        PairHolding::new(leg(asset_1), leg(asset_2))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trading::data::TradePair;
+    use chrono::TimeZone;
+
+    fn lot(ticker: &str, shares: i64, price: i64) -> Lot {
+        Lot {
+            ticker: ticker.to_string(),
+            fill_time: Utc.ymd(2021, 1, 4).and_hms(15, 0, 0),
+            price: Decimal::new(price, 0),
+            shares: Decimal::new(shares, 0),
+            strategy: Some("double-trouble".to_string()),
+            sub_strategy: Some(pair_key("AAPL", "MSFT")),
+        }
+    }
+
+    fn offset(offset: i64) -> LotOffset {
+        LotOffset {
+            topic: "lots".to_string(),
+            partition: 0,
+            offset,
+        }
+    }
+
+    fn bands(asset_1: &str, asset_2: &str) -> TradeBands {
+        TradeBands::new(
+            TradePair {
+                asset_1: asset_1.to_string(),
+                asset_2: asset_2.to_string(),
+                original_lt_spread: Decimal::ZERO,
+                original_st_spread: Decimal::ZERO,
+                epsilon: Decimal::ONE,
+                hedge_ratio: None,
+                dollars: None,
+            },
+            Decimal::ZERO,
+        )
+    }
+
+    #[test]
+    fn test_position_tracker() {
+        let mut tracker = PositionTracker::default();
+        assert_eq!(tracker.pair("AAPL", "MSFT"), PairHolding::Flat);
+        tracker.record(&lot("AAPL", 10, 100), offset(0));
+        assert_eq!(tracker.pair("AAPL", "MSFT"), PairHolding::Partial);
+        assert!(tracker
+            .pair("AAPL", "MSFT")
+            .requires_intents(&Position::Long));
+        tracker.record(&lot("AAPL", 30, 120), offset(1));
+        // Replayed on startup and then received again
+        tracker.record(&lot("AAPL", 30, 120), offset(1));
+        tracker.record(&lot("MSFT", -20, 200), offset(2));
+        assert_eq!(
+            tracker.ticker("AAPL"),
+            Holding {
+                shares: Decimal::new(40, 0),
+                average_price: Decimal::new(115, 0),
+            }
+        );
+        let holding = tracker.pair("AAPL", "MSFT");
+        assert_eq!(holding, PairHolding::Long);
+        assert!(!holding.requires_intents(&Position::Long));
+        assert!(!holding.requires_intents(&Position::RetainLong));
+        assert!(holding.requires_intents(&Position::RetainShort));
+        assert!(holding.requires_intents(&Position::Short));
+
+        // Reducing keeps the average price, flipping resets it
+        tracker.record(&lot("AAPL", -20, 130), offset(3));
+        assert_eq!(tracker.ticker("AAPL").average_price, Decimal::new(115, 0));
+        tracker.record(&lot("AAPL", -30, 90), offset(4));
+        assert_eq!(
+            tracker.ticker("AAPL"),
+            Holding {
+                shares: Decimal::new(-10, 0),
+                average_price: Decimal::new(90, 0),
+            }
+        );
+        assert_eq!(tracker.pair("AAPL", "MSFT"), PairHolding::Partial);
+    }
+
+    #[test]
+    fn test_unattributed_lots() {
+        let pairs = vec![bands("AAPL", "MSFT"), bands("GOOGL", "MSFT")];
+        let mut tracker = PositionTracker::new(&pairs);
+        let unattributed = |ticker, shares| Lot {
+            sub_strategy: None,
+            ..lot(ticker, shares, 100)
+        };
+        tracker.record(&unattributed("AAPL", 10), offset(0));
+        tracker.record(&unattributed("MSFT", -10), offset(1));
+        // MSFT is also traded by GOOGL-MSFT, so its shares can't be assigned to either pair
+        assert_eq!(tracker.pair("AAPL", "MSFT"), PairHolding::Partial);
+        assert_eq!(tracker.pair("GOOGL", "MSFT"), PairHolding::Flat);
+
+        let mut tracker = PositionTracker::new(&pairs[..1]);
+        tracker.record(&unattributed("AAPL", 10), offset(0));
+        tracker.record(&unattributed("MSFT", -10), offset(1));
+        assert_eq!(tracker.pair("AAPL", "MSFT"), PairHolding::Long);
+    }
+}
@@ -1,11 +1,19 @@
+use crate::trading::positions::{Lot, LotOffset};
+use crate::trading::WIND_DOWN_MINUTES;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
 use futures::prelude::*;
 use polygon::ws::{Aggregate, PolygonMessage};
-use rdkafka::consumer::StreamConsumer;
-use rdkafka::Message;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::{Message, Offset, TopicPartitionList};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use tokio::sync::mpsc::UnboundedSender;
-use tracing::{error, info, trace, warn};
+use tokio::time::timeout;
+use tracing::{debug, error, info, trace, warn};
+
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(tag = "state", rename_all = "lowercase")]
@@ -18,17 +26,85 @@ enum State {
 #[serde(untagged)]
 enum Input {
     MarketState(State),
+    Lot(Lot),
     Polygon(PolygonMessage),
 }
 
 #[derive(Debug)]
 pub(crate) enum RelayMessage {
     Agg(Aggregate),
+    Lot(Lot, LotOffset),
     WindDown,
 }
 
+/// Whether a lot belongs to double-trouble. Lots without an owner may still be ours.
+fn is_ours(lot: &Lot) -> bool {
+    !matches!(
+        lot.strategy.as_deref(),
+        Some(strategy) if strategy != "double-trouble"
+    )
+}
+
+fn lot_offset<M: Message>(message: &M) -> LotOffset {
+    LotOffset {
+        topic: message.topic().to_string(),
+        partition: message.partition(),
+        offset: message.offset(),
+    }
+}
+
+/// Our lots published on `topic` since `since`, so that positions held before a restart are
+/// known before trading resumes. `consumer` must not be subscribed to anything.
+#[tracing::instrument(skip(consumer))]
+pub(super) async fn replay_lots(
+    consumer: &StreamConsumer,
+    topic: &str,
+    since: DateTime<Utc>,
+) -> Result<Vec<(Lot, LotOffset)>> {
+    let metadata = consumer.fetch_metadata(Some(topic), REPLAY_TIMEOUT)?;
+    let mut timestamps = TopicPartitionList::new();
+    for partition in metadata.topics().iter().flat_map(|t| t.partitions()) {
+        timestamps.add_partition_offset(
+            topic,
+            partition.id(),
+            Offset::Offset(since.timestamp_millis()),
+        )?;
+    }
+    let offsets = consumer.offsets_for_times(timestamps, REPLAY_TIMEOUT)?;
+    // High watermark of each partition with lots left to read
+    let mut remaining = HashMap::new();
+    for element in offsets.elements() {
+        if let Offset::Offset(start) = element.offset() {
+            let (_, high) =
+                consumer.fetch_watermarks(topic, element.partition(), REPLAY_TIMEOUT)?;
+            if start < high {
+                remaining.insert(element.partition(), high);
+            }
+        }
+    }
+    consumer.assign(&offsets)?;
+
+    let mut lots = Vec::new();
+    while !remaining.is_empty() {
+        let message = timeout(REPLAY_TIMEOUT, consumer.recv()).await??;
+        let offset = lot_offset(&message);
+        match message.payload().map(serde_json::from_slice::<Lot>) {
+            Some(Ok(lot)) if is_ours(&lot) => lots.push((lot, offset.clone())),
+            Some(Ok(_)) => {}
+            Some(Err(e)) => error!("{:?}", e),
+            None => {}
+        }
+        if matches!(remaining.get(&offset.partition), Some(high) if offset.offset + 1 >= *high) {
+            remaining.remove(&offset.partition);
+        }
+    }
+    debug!(lots = lots.len(), "Replayed lots");
+    Ok(lots)
+}
+
 pub(super) struct Relay {
     tickers: HashSet<String>,
+    lots_topic: String,
     consumer: StreamConsumer,
     sender: UnboundedSender<RelayMessage>,
 }
@@ -36,11 +112,13 @@ pub(super) struct Relay {
 impl Relay {
     pub fn new(
         tickers: HashSet<String>,
+        lots_topic: String,
         consumer: StreamConsumer,
         sender: UnboundedSender<RelayMessage>,
     ) -> Self {
         Self {
             tickers,
+            lots_topic,
             consumer,
             sender,
         }
@@ -61,9 +139,11 @@ impl Relay {
                 }
             })
             .filter_map(|message| async move {
+                let offset = lot_offset(&message);
                 message
                     .payload()
                     .map(|bytes| serde_json::from_slice::<Input>(bytes))
+                    .map(|res| res.map(|input| (input, offset)))
             })
             .filter_map(|res| async move {
                 match res {
@@ -74,7 +154,7 @@ impl Relay {
                     }
                 }
             })
-            .for_each_concurrent(50, |parsed| async move {
+            .for_each_concurrent(50, |(parsed, offset)| async move {
                 match parsed {
                     Input::Polygon(PolygonMessage::Second(agg)) => {
                         if self.tickers.contains(&agg.symbol) {
@@ -85,8 +165,20 @@ impl Relay {
                             }
                         }
                     }
+                    Input::Lot(lot) => {
+                        if offset.topic == self.lots_topic
+                            && is_ours(&lot)
+                            && self.tickers.contains(&lot.ticker)
+                        {
+                            trace!("{:?}", lot);
+                            let res = self.sender.send(RelayMessage::Lot(lot, offset));
+                            if let Err(e) = res {
+                                error!("{:?}", e);
+                            }
+                        }
+                    }
                     Input::MarketState(State::Open { next_close }) => {
-                        if next_close <= 600 {
+                        if next_close as i64 <= WIND_DOWN_MINUTES * 60 {
                             info!("Market closing soon, winding down");
                             let res = self.sender.send(RelayMessage::WindDown);
                             if let Err(e) = res {
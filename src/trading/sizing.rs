@@ -0,0 +1,165 @@
+use crate::settings::{SizingMode, TradingSettings};
+use crate::trading::domain::TradeBands;
+use crate::trading::positions::pair_key;
+use rust_decimal::prelude::*;
+use std::collections::HashMap;
+
+/// Decides how many dollars to commit to a pair, split between its legs according to
+/// `LegSizing`.
+pub trait SizingPolicy: Send + Sync {
+    fn pair_dollars(&self, pair: &TradeBands) -> Decimal;
+}
+
+/// The same dollars for every pair.
+pub struct FixedDollars {
+    dollars: Decimal,
+}
+
+impl SizingPolicy for FixedDollars {
+    fn pair_dollars(&self, _pair: &TradeBands) -> Decimal {
+        self.dollars
+    }
+}
+
+/// `cash` split equally across the day's pairs.
+pub struct EqualSplit {
+    dollars: Decimal,
+}
+
+impl EqualSplit {
+    pub fn new(cash: Decimal, pairs: usize) -> Self {
+        Self {
+            dollars: cash / Decimal::from(pairs.max(1)),
+        }
+    }
+}
+
+impl SizingPolicy for EqualSplit {
+    fn pair_dollars(&self, _pair: &TradeBands) -> Decimal {
+        self.dollars
+    }
+}
+
+/// `cash` split across the day's pairs in proportion to the inverse of their calibrated band
+/// width, so that wider, more volatile spreads get fewer dollars.
+pub struct InverseVolatility {
+    dollars: HashMap<String, Decimal>,
+}
+
+impl InverseVolatility {
+    pub fn new(cash: Decimal, pairs: &[TradeBands]) -> Self {
+        let weights: Vec<(String, Decimal)> = pairs
+            .iter()
+            .filter(|pair| pair.upper_band > pair.equilibrium)
+            .map(|pair| {
+                let weight = Decimal::ONE / (pair.upper_band - pair.equilibrium);
+                (pair_key(&pair.asset_1, &pair.asset_2), weight)
+            })
+            .collect();
+        let total: Decimal = weights.iter().map(|(_, weight)| *weight).sum();
+        let dollars = weights
+            .into_iter()
+            .map(|(key, weight)| (key, cash * weight / total))
+            .collect();
+        Self { dollars }
+    }
+}
+
+impl SizingPolicy for InverseVolatility {
+    fn pair_dollars(&self, pair: &TradeBands) -> Decimal {
+        self.dollars
+            .get(&pair_key(&pair.asset_1, &pair.asset_2))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// The `dollars` column of the pair file where set, `default` otherwise.
+pub struct PerPairOverride {
+    default: Box<dyn SizingPolicy>,
+}
+
+impl SizingPolicy for PerPairOverride {
+    fn pair_dollars(&self, pair: &TradeBands) -> Decimal {
+        pair.dollars
+            .unwrap_or_else(|| self.default.pair_dollars(pair))
+    }
+}
+
+/// The configured sizing policy for the day's `pairs`.
+pub fn sizing_policy(
+    settings: &TradingSettings,
+    cash: Decimal,
+    pairs: &[TradeBands],
+) -> Box<dyn SizingPolicy> {
+    let default: Box<dyn SizingPolicy> = match settings.sizing {
+        SizingMode::Fixed => Box::new(FixedDollars {
+            // Historically a quarter of cash in each leg
+            dollars: settings
+                .pair_dollars
+                .unwrap_or_else(|| cash / Decimal::new(2, 0)),
+        }),
+        SizingMode::EqualSplit => Box::new(EqualSplit::new(cash, pairs.len())),
+        SizingMode::InverseVolatility => Box::new(InverseVolatility::new(cash, pairs)),
+    };
+    Box::new(PerPairOverride { default })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::trading::data::TradePair;
+
+    fn bands(asset_1: &str, epsilon: i64, dollars: Option<Decimal>) -> TradeBands {
+        TradeBands::new(
+            TradePair {
+                asset_1: asset_1.to_string(),
+                asset_2: "SPY".to_string(),
+                original_lt_spread: Decimal::ZERO,
+                original_st_spread: Decimal::ZERO,
+                epsilon: Decimal::new(epsilon, 2),
+                hedge_ratio: None,
+                dollars,
+            },
+            Decimal::ZERO,
+        )
+    }
+
+    #[test]
+    fn test_sizing_policies() {
+        let pairs = vec![
+            bands("AAPL", 1, None),
+            bands("MSFT", 3, None),
+            bands("XOM", 3, Some(Decimal::new(50, 0))),
+        ];
+        let cash = Decimal::new(1000, 0);
+        let sizes = |sizing| {
+            let settings = TradingSettings {
+                sizing,
+                ..TradingSettings::default()
+            };
+            let policy = sizing_policy(&settings, cash, &pairs);
+            pairs
+                .iter()
+                .map(|pair| policy.pair_dollars(pair).round_dp(2))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            sizes(SizingMode::Fixed),
+            vec![
+                Decimal::new(500, 0),
+                Decimal::new(500, 0),
+                Decimal::new(50, 0)
+            ]
+        );
+        assert_eq!(
+            sizes(SizingMode::EqualSplit)[..2],
+            [Decimal::new(33333, 2), Decimal::new(33333, 2)]
+        );
+        // Weights of 100, 33.3 and 33.3
+        assert_eq!(
+            sizes(SizingMode::InverseVolatility)[..2],
+            [Decimal::new(600, 0), Decimal::new(200, 0)]
+        );
+    }
+}
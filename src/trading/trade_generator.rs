@@ -1,30 +1,45 @@
+use crate::calendar::{session, trading_date};
+use crate::settings::{LegSizing, TradingSettings};
 use crate::trading::domain::Position;
+use crate::trading::netting::{is_filled, net_targets, LegTarget, NettedHoldings};
+use crate::trading::positions::{pair_key, PositionTracker};
 use crate::trading::relay::RelayMessage;
-use crate::trading::TradeBands;
-use chrono::{Local, NaiveTime, TimeZone, Utc};
-use chrono_tz::US::Eastern;
+use crate::trading::sizing::SizingPolicy;
+use crate::trading::{wind_down_time, TradeBands};
+use chrono::{DateTime, Utc};
 use polygon::ws::Aggregate;
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use rust_decimal::prelude::*;
 use std::collections::HashMap;
 use tokio::sync::mpsc::UnboundedReceiver;
-use tokio::time::{interval_at, Duration, Instant, Interval};
+use tokio::time::{interval_at, sleep, Duration, Instant, Interval};
 use tracing::{debug, error, info, trace, warn};
 use trading_base::{Amount, Identifier, PositionIntent, UpdatePolicy};
 
 pub(super) struct TradeGenerator {
-    cash: Decimal,
+    sizing: Box<dyn SizingPolicy>,
     pairs: Vec<TradeBands>,
+    leg_sizing: LegSizing,
+    net_intents: bool,
+    /// Fraction of the way back to equilibrium at which held pairs go flat.
+    exit_fraction: Option<Decimal>,
     prices: HashMap<String, Decimal>,
+    positions: PositionTracker,
+    /// Holdings of pairs whose intents are netted, which their lots can't be attributed to.
+    netted_holdings: NettedHoldings,
     receiver: UnboundedReceiver<RelayMessage>,
     producer: FutureProducer,
     interval: Interval,
+    /// Deadline for today's intents, after which positions are wound down.
+    wind_down_at: DateTime<Utc>,
 }
 
 impl TradeGenerator {
     pub fn new(
-        cash: Decimal,
+        sizing: Box<dyn SizingPolicy>,
         pairs: Vec<TradeBands>,
+        settings: &TradingSettings,
+        positions: PositionTracker,
         receiver: UnboundedReceiver<RelayMessage>,
         producer: FutureProducer,
     ) -> Self {
@@ -35,13 +50,24 @@ impl TradeGenerator {
             // ...and then every 5 minutes thereafter
             Duration::from_secs(60 * 5),
         );
+        let today = trading_date(&Utc::now());
+        if session(today).is_none() {
+            warn!(%today, "The exchange is closed today");
+        }
+        let wind_down_at = wind_down_time(today);
         Self {
-            cash,
+            sizing,
             pairs,
+            leg_sizing: settings.leg_sizing,
+            net_intents: settings.net_intents,
+            exit_fraction: settings.exit_fraction(),
             prices,
+            positions,
+            netted_holdings: NettedHoldings::default(),
             receiver,
             producer,
             interval,
+            wind_down_at,
         }
     }
 
@@ -50,27 +76,56 @@ impl TradeGenerator {
     }
 
     #[tracing::instrument(skip(self))]
-    fn generate_positions(&self) -> Vec<PositionIntent> {
+    fn generate_positions(&mut self) -> Vec<PositionIntent> {
         trace!("Generating positions");
         let mut intents = Vec::new();
-        let before_time = Eastern
-            .from_local_date(&Local::today().naive_local())
-            .and_time(NaiveTime::from_hms(15, 50, 0))
-            .unwrap()
-            .with_timezone(&Utc);
+        let mut targets = Vec::new();
+        let before_time = self.wind_down_at;
         for pair in self.pairs.iter() {
             let p1 = self.prices.get(&pair.asset_1);
             let p2 = self.prices.get(&pair.asset_2);
-            let pair_string = format!("{}-{}", pair.asset_1.clone(), pair.asset_2.clone());
+            let pair_string = pair_key(&pair.asset_1, &pair.asset_2);
             if let Some((p1, p2)) = p1.zip(p2) {
-                let position = pair.trade_signal(p1, p2);
+                let positions = &self.positions;
+                let tracked = || positions.pair(&pair.asset_1, &pair.asset_2);
+                let holding = if self.net_intents {
+                    self.netted_holdings.holding(&pair_string, tracked)
+                } else {
+                    tracked()
+                };
+                let position = pair.signal(p1, p2, holding, self.exit_fraction);
+                info!(pair = %pair_string, ?holding, signal = ?position, "Pair position");
+                let changed = holding.requires_intents(&position);
+                let (dollars_1, dollars_2) =
+                    pair.leg_dollars(self.sizing.pair_dollars(pair), self.leg_sizing);
+                if self.net_intents {
+                    let (target_1, target_2) = self.netted_holdings.targets(
+                        &pair_string,
+                        &position,
+                        holding,
+                        (dollars_1, dollars_2),
+                    );
+                    for (ticker, dollars) in [(&pair.asset_1, target_1), (&pair.asset_2, target_2)]
+                    {
+                        targets.push(LegTarget {
+                            pair: pair_string.clone(),
+                            ticker: ticker.clone(),
+                            dollars,
+                            changed,
+                        });
+                    }
+                    continue;
+                }
+                if !changed {
+                    continue;
+                }
                 match position {
                     Position::Long => {
                         intents.push(
                             PositionIntent::builder(
                                 "double-trouble".to_string(),
                                 pair.asset_1.clone(),
-                                Amount::Dollars(self.cash / Decimal::new(4, 0)),
+                                Amount::Dollars(dollars_1),
                             )
                             .update_policy(UpdatePolicy::RetainLong)
                             .limit_price(p1 * Decimal::new(1005, 3))
@@ -83,7 +138,7 @@ impl TradeGenerator {
                             PositionIntent::builder(
                                 "double-trouble".to_string(),
                                 pair.asset_2.clone(),
-                                Amount::Dollars(-self.cash / Decimal::new(4, 0)),
+                                Amount::Dollars(-dollars_2),
                             )
                             .update_policy(UpdatePolicy::RetainShort)
                             .limit_price(p2 * Decimal::new(995, 3))
@@ -98,7 +153,7 @@ impl TradeGenerator {
                             PositionIntent::builder(
                                 "double-trouble".to_string(),
                                 pair.asset_1.clone(),
-                                Amount::Dollars(-self.cash / Decimal::new(4, 0)),
+                                Amount::Dollars(-dollars_1),
                             )
                             .update_policy(UpdatePolicy::RetainShort)
                             .limit_price(p1 * Decimal::new(995, 3))
@@ -111,7 +166,7 @@ impl TradeGenerator {
                             PositionIntent::builder(
                                 "double-trouble".to_string(),
                                 pair.asset_2.clone(),
-                                Amount::Dollars(self.cash / Decimal::new(4, 0)),
+                                Amount::Dollars(dollars_2),
                             )
                             .update_policy(UpdatePolicy::RetainLong)
                             .limit_price(p2 * Decimal::new(1005, 3))
@@ -173,12 +228,72 @@ impl TradeGenerator {
                             .expect("Always works"),
                         );
                     }
+                    Position::Flat => {
+                        for asset in [&pair.asset_1, &pair.asset_2] {
+                            intents.push(
+                                PositionIntent::builder(
+                                    "double-trouble".to_string(),
+                                    asset.clone(),
+                                    Amount::Zero,
+                                )
+                                .sub_strategy(pair_string.clone())
+                                .before(before_time)
+                                .build()
+                                .expect("Always works"),
+                            );
+                        }
+                    }
                 }
             }
         }
+        if self.net_intents {
+            intents.extend(self.netted_intents(targets));
+        }
         intents
     }
 
+    /// One intent per ticker for its exposure summed across pairs, skipping tickers whose pairs
+    /// all hold what their signals want and whose tracked position already fills the target.
+    fn netted_intents(&self, targets: Vec<LegTarget>) -> Vec<PositionIntent> {
+        net_targets(targets)
+            .into_iter()
+            .filter(|target| {
+                let shares = self.positions.ticker(&target.ticker).shares;
+                target.changed || !is_filled(shares, target.dollars)
+            })
+            .filter_map(|target| {
+                info!(
+                    ticker = %target.ticker,
+                    dollars = %target.dollars,
+                    attribution = ?target.attribution,
+                    "Netted target"
+                );
+                let price = self.prices.get(&target.ticker)?;
+                let builder = if target.dollars.is_zero() {
+                    PositionIntent::builder("double-trouble", target.ticker, Amount::Zero)
+                } else {
+                    let limit = if target.dollars > Decimal::ZERO {
+                        Decimal::new(1005, 3)
+                    } else {
+                        Decimal::new(995, 3)
+                    };
+                    PositionIntent::builder(
+                        "double-trouble",
+                        target.ticker,
+                        Amount::Dollars(target.dollars),
+                    )
+                    .limit_price(price * limit)
+                };
+                Some(
+                    builder
+                        .before(self.wind_down_at)
+                        .build()
+                        .expect("Always works"),
+                )
+            })
+            .collect()
+    }
+
     async fn send_intents(&self, intents: Vec<PositionIntent>) {
         for intent in intents {
             debug!("Sending intent {:?}", intent);
@@ -226,8 +341,24 @@ impl TradeGenerator {
 
     pub async fn run(&mut self) {
         info!("Starting TradeGenerator");
+        let until_wind_down = (self.wind_down_at - Utc::now()).to_std().ok();
+        // Winding down now would flatten positions placed by hand since the deadline passed
+        let scheduled = until_wind_down.is_some();
+        if !scheduled {
+            warn!(
+                wind_down_at = %self.wind_down_at,
+                "Started after the wind-down time, skipping it"
+            );
+        }
+        let wind_down = sleep(until_wind_down.unwrap_or_else(|| Duration::from_secs(0)));
+        tokio::pin!(wind_down);
         loop {
             tokio::select! {
+                _ = &mut wind_down, if scheduled => {
+                    info!("Winding down ahead of the close");
+                    self.wind_down().await;
+                    return
+                },
                 _ = self.interval.tick() => {
                     trace!("Tick");
                     let intents = self.generate_positions();
@@ -238,6 +369,9 @@ impl TradeGenerator {
                         Some(RelayMessage::Agg(agg)) => {
                             self.update_price(agg)
                         },
+                        Some(RelayMessage::Lot(lot, offset)) => {
+                            self.positions.record(&lot, offset)
+                        },
                         Some(RelayMessage::WindDown) => {
                             self.wind_down().await;
                             return
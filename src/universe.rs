@@ -0,0 +1,301 @@
+use crate::calendar::trading_date;
+use crate::data_download::PriceData;
+use crate::settings::UniverseSettings;
+use crate::trading::data::TradePair;
+use anyhow::Result;
+use chrono::NaiveDate;
+use csv::{Reader, Writer};
+use rust_decimal::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::Path;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Member {
+    pub ticker: String,
+    #[serde(default)]
+    pub sector: Option<String>,
+    #[serde(default)]
+    pub exchange: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// CSV form of `Member`, with `tags` separated by semicolons.
+#[derive(Debug, Deserialize, Serialize)]
+struct MemberRow {
+    ticker: String,
+    sector: Option<String>,
+    exchange: Option<String>,
+    tags: Option<String>,
+}
+
+impl From<MemberRow> for Member {
+    fn from(row: MemberRow) -> Self {
+        Self {
+            ticker: row.ticker,
+            sector: row.sector,
+            exchange: row.exchange,
+            tags: row
+                .tags
+                .map(|tags| {
+                    tags.split(';')
+                        .map(str::trim)
+                        .filter(|tag| !tag.is_empty())
+                        .map(From::from)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl From<&Member> for MemberRow {
+    fn from(member: &Member) -> Self {
+        Self {
+            ticker: member.ticker.clone(),
+            sector: member.sector.clone(),
+            exchange: member.exchange.clone(),
+            tags: Some(member.tags.join(";")),
+        }
+    }
+}
+
+/// A ticker dropped by the universe filters, and why.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Exclusion {
+    pub ticker: String,
+    pub reason: String,
+}
+
+fn is_json<T: AsRef<Path>>(path: T) -> bool {
+    path.as_ref().extension() == Some(OsStr::new("json"))
+}
+
+/// Tickers to download, calibrate and trade, with their metadata.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Universe {
+    pub members: Vec<Member>,
+}
+
+impl Universe {
+    /// Read a universe from a `.json` list of members or a CSV of `ticker,sector,exchange,tags`.
+    pub fn read<T: AsRef<Path>>(path: T) -> Result<Self> {
+        let members = if is_json(&path) {
+            serde_json::from_reader(File::open(path)?)?
+        } else {
+            let rows: Result<Vec<MemberRow>, csv::Error> =
+                Reader::from_path(path)?.deserialize().collect();
+            rows?.into_iter().map(Member::from).collect()
+        };
+        Ok(Self { members })
+    }
+
+    /// The configured universe file, or a universe of `tickers` without metadata.
+    pub fn load(settings: &UniverseSettings, tickers: &[String]) -> Result<Self> {
+        match settings.file.as_ref() {
+            Some(file) => Self::read(file),
+            None => Ok(Self::from_tickers(tickers)),
+        }
+    }
+
+    pub fn from_tickers(tickers: &[String]) -> Self {
+        Self {
+            members: tickers
+                .iter()
+                .map(|ticker| Member {
+                    ticker: ticker.clone(),
+                    sector: None,
+                    exchange: None,
+                    tags: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn write<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        if is_json(&path) {
+            serde_json::to_writer(File::create(path)?, &self.members)?;
+        } else {
+            let mut writer = Writer::from_path(path)?;
+            for member in self.members.iter() {
+                writer.serialize(MemberRow::from(member))?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn tickers(&self) -> Vec<String> {
+        self.members.iter().map(|m| m.ticker.clone()).collect()
+    }
+
+    pub fn get(&self, ticker: &str) -> Option<&Member> {
+        self.members.iter().find(|m| m.ticker == ticker)
+    }
+
+    /// Keep the members whose downloaded prices pass the minimum price and average daily dollar
+    /// volume filters. Members without prices are dropped.
+    pub fn filter(
+        &self,
+        prices: &PriceData,
+        settings: &UniverseSettings,
+    ) -> (Self, Vec<Exclusion>) {
+        let mut members = Vec::new();
+        let mut exclusions = Vec::new();
+        for member in self.members.iter() {
+            match exclusion_reason(prices, &member.ticker, settings) {
+                Some(reason) => exclusions.push(Exclusion {
+                    ticker: member.ticker.clone(),
+                    reason,
+                }),
+                None => members.push(member.clone()),
+            }
+        }
+        (Self { members }, exclusions)
+    }
+
+    /// Drop pairs with a leg outside the universe.
+    pub fn retain_pairs(&self, pairs: Vec<TradePair>) -> Vec<TradePair> {
+        pairs
+            .into_iter()
+            .filter(|pair| {
+                let included =
+                    self.get(&pair.asset_1).is_some() && self.get(&pair.asset_2).is_some();
+                if !included {
+                    info!(
+                        asset_1 = %pair.asset_1,
+                        asset_2 = %pair.asset_2,
+                        "Dropping pair outside the universe"
+                    );
+                }
+                included
+            })
+            .collect()
+    }
+}
+
+fn exclusion_reason(
+    prices: &PriceData,
+    ticker: &str,
+    settings: &UniverseSettings,
+) -> Option<String> {
+    let bars = match prices.get(ticker) {
+        Some(bars) if !bars.is_empty() => bars,
+        _ => return Some("no prices".to_string()),
+    };
+    let last_close = bars[bars.len() - 1].close;
+    if let Some(min_price) = settings.min_price {
+        if last_close < min_price {
+            return Some(format!("last close {} below {}", last_close, min_price));
+        }
+    }
+    if let Some(min_dollar_volume) = settings.min_dollar_volume {
+        let mut days: BTreeMap<NaiveDate, Decimal> = BTreeMap::new();
+        for bar in bars {
+            *days.entry(trading_date(&bar.timestamp)).or_default() +=
+                bar.vwap.unwrap_or(bar.close) * bar.volume;
+        }
+        let average = days.values().sum::<Decimal>() / Decimal::from(days.len());
+        if average < min_dollar_volume {
+            return Some(format!(
+                "average daily dollar volume {} below {}",
+                average.round_dp(2),
+                min_dollar_volume
+            ));
+        }
+    }
+    None
+}
+
+/// Apply the universe filters to `prices`, dropping excluded tickers and writing the filtered
+/// universe if configured.
+pub fn filter_universe(
+    universe: &Universe,
+    mut prices: PriceData,
+    settings: &UniverseSettings,
+) -> Result<PriceData> {
+    let (filtered, exclusions) = universe.filter(&prices, settings);
+    for exclusion in exclusions.iter() {
+        warn!(
+            ticker = %exclusion.ticker,
+            reason = %exclusion.reason,
+            "Excluding ticker from the universe"
+        );
+    }
+    prices.retain(|ticker, _| filtered.get(ticker).is_some());
+    if let Some(filtered_file) = settings.filtered_file.as_ref() {
+        filtered.write(filtered_file)?;
+    }
+    Ok(prices)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::data_download::{Bar, Session};
+    use chrono::{TimeZone, Utc};
+    use std::fs::write;
+
+    fn bar(day: u32, close: i64, volume: i64) -> Bar {
+        Bar {
+            timestamp: Utc.ymd(2021, 1, day).and_hms(15, 0, 0),
+            open: Decimal::new(close, 0),
+            high: Decimal::new(close, 0),
+            low: Decimal::new(close, 0),
+            close: Decimal::new(close, 0),
+            volume: Decimal::new(volume, 0),
+            vwap: None,
+            transactions: None,
+            session: Session::Regular,
+        }
+    }
+
+    #[test]
+    fn test_universe() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("universe.csv");
+        write(
+            &path,
+            "ticker,sector,exchange,tags\n\
+             AAPL,Technology,XNAS,large_cap;dividend\n\
+             PENNY,Energy,XNYS,\n\
+             THIN,Energy,XNYS,\n\
+             GONE,,,\n",
+        )
+        .unwrap();
+        let universe = Universe::read(&path).unwrap();
+        assert_eq!(universe.members.len(), 4);
+        assert_eq!(
+            universe.get("AAPL").unwrap().tags,
+            vec!["large_cap".to_string(), "dividend".to_string()]
+        );
+        assert_eq!(universe.get("GONE").unwrap().sector, None);
+
+        let mut prices = PriceData::new();
+        prices.insert(
+            "AAPL".to_string(),
+            vec![bar(4, 100, 1000), bar(5, 100, 3000)],
+        );
+        prices.insert("PENNY".to_string(), vec![bar(4, 1, 1_000_000)]);
+        prices.insert("THIN".to_string(), vec![bar(4, 50, 10)]);
+        let settings = UniverseSettings {
+            min_price: Some(Decimal::new(5, 0)),
+            min_dollar_volume: Some(Decimal::new(100_000, 0)),
+            ..UniverseSettings::default()
+        };
+        let (filtered, exclusions) = universe.filter(&prices, &settings);
+        assert_eq!(filtered.tickers(), vec!["AAPL".to_string()]);
+        let excluded: Vec<&str> = exclusions.iter().map(|e| e.ticker.as_str()).collect();
+        assert_eq!(excluded, vec!["PENNY", "THIN", "GONE"]);
+
+        let json = dir.path().join("universe.json");
+        filtered.write(&json).unwrap();
+        assert_eq!(Universe::read(&json).unwrap(), filtered);
+
+        dir.close().unwrap();
+    }
+}